@@ -1,33 +1,39 @@
-use glam::Vec3A;
-use tinyrenderer::objloader::{Mesh, load_obj};
-use tinyrenderer::util::{draw_mesh};
-use tinyrenderer::renderer::Renderer;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use tinyrenderer::geometry::{Matrix4, Vector3};
+use tinyrenderer::light::Light;
+use tinyrenderer::objloader::load_obj;
+use tinyrenderer::renderer::{Material, NormalSpace, Renderer, projection, look_at_glam};
+use tinyrenderer::shader::{NormalMappedShader, Uniforms};
+use tinyrenderer::util::load_png_texture;
+use criterion::{criterion_group, criterion_main, Criterion};
 
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("draw_filled", |b| {
+    c.bench_function("draw_normal_mapped", |b| {
         const WIDTH: i32 = 800;
         const HEIGHT: i32 = 800;
         let mut r = Renderer::new(WIDTH, HEIGHT);
         let mesh = load_obj("obj/african_head.obj");
-        
-        b.iter(|| draw_mesh(&mut r, &mesh))
-    });
-    c.bench_function("draw_filled_glam", |b| {
-        const WIDTH: i32 = 800;
-        const HEIGHT: i32 = 800;
-        let mut r = Renderer::new(WIDTH, HEIGHT);
-        let mesh: Mesh<Vec3A> = load_obj("obj/african_head.obj");
-        
-        b.iter(|| draw_mesh(&mut r, &mesh))
-    });
-    c.bench_function("draw_wireframe", |b| {
-        const WIDTH: i32 = 800;
-        const HEIGHT: i32 = 800;
-        let mut r = Renderer::new(WIDTH, HEIGHT);
-        let mesh = load_obj("obj/african_head.obj");
-        
-        b.iter(|| draw_mesh(&mut r, &mesh))
+        let material = Material::new(
+            load_png_texture("obj/african_head_diffuse.png"),
+            load_png_texture("obj/african_head_nm_tangent.png"),
+            NormalSpace::Tangent,
+        );
+        let eye = Vector3::new(1.0, 1.0, 3.0);
+        let lights = [Light::directional(Vector3::ONE)];
+        let shader = NormalMappedShader {
+            uniforms: Uniforms::new(
+                projection(eye.length()),
+                look_at_glam(eye, Vector3::ZERO, Vector3::Y),
+                Matrix4::IDENTITY,
+                eye,
+                &lights,
+                &material,
+            ),
+        };
+
+        b.iter(|| {
+            r.clear();
+            r.draw_mesh(&mesh, &shader, ..)
+        })
     });
 }
 
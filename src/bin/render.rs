@@ -0,0 +1,176 @@
+#![deny(clippy::all)]
+#![forbid(unsafe_code)]
+
+//! Headless renderer: draws a scene to a PNG without opening a window.
+//!
+//!     render --scene scenes/african_head.scene --shader pbr --size 800x600 --output head.png
+
+use std::env;
+use std::process;
+
+use tinyrenderer::environment::Environment;
+use tinyrenderer::geometry::Vector3;
+use tinyrenderer::light::Light;
+use tinyrenderer::objloader::read_obj;
+use tinyrenderer::renderer::{Material, NormalSpace, Object, Renderer, RendererState};
+use tinyrenderer::scene::{Scene, load_scene, name_key, parse_size, save_scene};
+use tinyrenderer::shader::ShaderKind;
+use tinyrenderer::util::{read_png_texture, write_png};
+
+const USAGE: &str = "\
+Usage: render [options]
+
+Options override the scene they apply to.
+
+  --scene PATH          scene file, otherwise an empty scene lit from 1,1,1
+  --mesh PATH           OBJ mesh of the first object
+  --diffuse PATH        diffuse texture of the first object
+  --normal PATH         normal map of the first object
+  --normal-space SPACE  tangent or object
+  --environment PATH    equirectangular environment, Radiance .hdr or PNG
+  --eye X,Y,Z           camera position
+  --center X,Y,Z        point looked at
+  --up X,Y,Z            camera up
+  --light X,Y,Z         directional light from direction X,Y,Z, may be repeated, replaces the scene's lights
+  --point-light X,Y,Z   point light at X,Y,Z, may be repeated, replaces the scene's lights
+  --size WxH            image size in pixels, at most 16384 a side (default 1000x1000)
+  --shader NAME         flat, gouraud, phong, normal-mapped, toon, unlit or pbr
+  --output PATH         PNG to write (default out.png)
+  --depth PATH          also write the depth buffer to a PNG
+  --save-scene PATH     also write the scene with the options applied
+  --help                print this message";
+
+#[derive(Default)]
+struct Args {
+    scene: Option<String>,
+    mesh: Option<String>,
+    diffuse: Option<String>,
+    normal: Option<String>,
+    normal_space: Option<NormalSpace>,
+    environment: Option<String>,
+    eye: Option<Vector3>,
+    center: Option<Vector3>,
+    up: Option<Vector3>,
+    lights: Vec<Light>,
+    size: Option<(i32, i32)>,
+    shader: Option<ShaderKind>,
+    output: Option<String>,
+    depth: Option<String>,
+    save_scene: Option<String>,
+}
+
+fn parse_vec3(s: &str) -> Result<Vector3, String> {
+    let v: Vec<f32> = s.split(',').map(|c| c.trim().parse::<f32>()).collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid vector '{}': {}", s, e))?;
+    match v[..] {
+        [x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => Err(format!("expected X,Y,Z, got '{}'", s)),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+
+    while let Some(flag) = args.next() {
+        if flag == "--help" || flag == "-h" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--scene" => parsed.scene = Some(value),
+            "--mesh" => parsed.mesh = Some(value),
+            "--diffuse" => parsed.diffuse = Some(value),
+            "--normal" => parsed.normal = Some(value),
+            "--normal-space" => parsed.normal_space = Some(match value.as_str() {
+                "tangent" => NormalSpace::Tangent,
+                "object" => NormalSpace::Object,
+                _ => return Err(format!("unknown normal space '{}'", value)),
+            }),
+            "--environment" => parsed.environment = Some(value),
+            "--eye" => parsed.eye = Some(parse_vec3(&value)?),
+            "--center" => parsed.center = Some(parse_vec3(&value)?),
+            "--up" => parsed.up = Some(parse_vec3(&value)?),
+            "--light" => parsed.lights.push(Light::directional(parse_vec3(&value)?)),
+            "--point-light" => parsed.lights.push(Light::point(parse_vec3(&value)?)),
+            "--size" => parsed.size = Some(parse_size(&value)?),
+            "--shader" => parsed.shader = Some(ShaderKind::ALL.into_iter()
+                .find(|kind| name_key(kind.name()) == value)
+                .ok_or_else(|| format!("unknown shader '{}'", value))?),
+            "--output" => parsed.output = Some(value),
+            "--depth" => parsed.depth = Some(value),
+            "--save-scene" => parsed.save_scene = Some(value),
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let texture = |path: &str| read_png_texture(path).map_err(|e| format!("{}: {}", path, e));
+    let scene = match &args.scene {
+        Some(path) => load_scene(path)?,
+        None => Scene {
+            state: RendererState { lights: vec![Light::directional(Vector3::ONE)], ..RendererState::default() },
+            size: None,
+        },
+    };
+    let mut state = scene.state;
+
+    if let Some(path) = &args.mesh {
+        let mesh = read_obj(path).map_err(|e| format!("{}: {}", path, e))?;
+        match state.objects.first_mut() {
+            Some(object) => object.mesh = mesh.into(),
+            None => state.objects.push(Object::new(mesh, Material::default())),
+        }
+    }
+    if let Some(object) = state.objects.first_mut() {
+        let material = &mut object.material;
+        if let Some(path) = &args.diffuse {
+            material.diffuse = texture(path)?.into();
+        }
+        if let Some(normal_space) = args.normal_space {
+            material.set_normal_space(normal_space);
+        }
+        if let Some(path) = &args.normal {
+            material.normal = texture(path)?.into();
+            material.alternate_normal = None;
+        }
+    }
+    if let Some(path) = &args.environment {
+        state.environment = Some(Environment::load(path).map_err(|e| format!("{}: {}", path, e))?);
+    }
+
+    let camera = state.camera_mut();
+    camera.eye = args.eye.unwrap_or(camera.eye);
+    camera.center = args.center.unwrap_or(camera.center);
+    camera.up = args.up.unwrap_or(camera.up);
+    if !args.lights.is_empty() {
+        state.lights = args.lights;
+    }
+    state.shader = args.shader.unwrap_or(state.shader);
+    let (width, height) = args.size.or(scene.size).unwrap_or((1000, 1000));
+
+    if let Some(path) = &args.save_scene {
+        save_scene(path, &state, Some((width, height)))?;
+    }
+
+    let mut renderer = Renderer::new(width, height);
+    renderer.draw_mesh_shader(&state);
+
+    let save = |path: &str, buf: &[u32]| write_png(path, width as u32, height as u32, buf).map_err(|e| format!("{}: {}", path, e));
+    save(args.output.as_deref().unwrap_or("out.png"), &renderer.buf)?;
+    if let Some(depth) = &args.depth {
+        save(depth, &renderer.zbuf_buf())?;
+    }
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(e) = parse_args(env::args().skip(1)).and_then(run) {
+        eprintln!("render: {}\n\n{}", e, USAGE);
+        process::exit(2);
+    }
+}
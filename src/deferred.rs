@@ -0,0 +1,143 @@
+use crate::geometry::{Vector3, Vector4};
+use crate::renderer::{Renderer, Vertex};
+use crate::shader::{Fragment, PhongVarying, Shader, Surface, Uniforms, phong_vertex};
+use crate::util::{color_from_linear, color_from_vec4, srgb_to_linear};
+
+/// Material id of pixels no surface was written to
+pub const NO_MATERIAL: u32 = u32::MAX;
+
+/// Per-pixel surface attributes written by the rasterizer, in the same
+/// layout as `Renderer::buf`.  Depth is kept in `Renderer::zbuf`.
+pub struct GBuffer {
+    pub position: Vec<Vector3>,
+    pub normal: Vec<Vector3>,
+    pub albedo: Vec<Vector3>,
+    pub metallic: Vec<f32>,
+    pub roughness: Vec<f32>,
+    pub material: Vec<u32>,
+}
+
+impl GBuffer {
+    pub fn new(len: usize) -> Self {
+        Self {
+            position: vec![Vector3::ZERO; len],
+            normal: vec![Vector3::ZERO; len],
+            albedo: vec![Vector3::ZERO; len],
+            metallic: vec![0.0; len],
+            roughness: vec![0.0; len],
+            material: vec![NO_MATERIAL; len],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.position.fill(Vector3::ZERO);
+        self.normal.fill(Vector3::ZERO);
+        self.albedo.fill(Vector3::ZERO);
+        self.metallic.fill(0.0);
+        self.roughness.fill(0.0);
+        self.material.fill(NO_MATERIAL);
+    }
+
+    pub fn write(&mut self, index: usize, surface: &Surface) {
+        self.position[index] = surface.position;
+        self.normal[index] = surface.normal;
+        self.albedo[index] = surface.albedo;
+        self.metallic[index] = surface.metallic;
+        self.roughness[index] = surface.roughness;
+        self.material[index] = surface.material;
+    }
+
+    //  whether a surface was written to the pixel
+    pub fn covered(&self, index: usize) -> bool {
+        self.material[index] != NO_MATERIAL
+    }
+
+    //  world positions scaled so [-2, 2] maps to colors
+    pub fn position_buf(&self) -> Vec<u32> {
+        self.debug_buf(|i| self.position[i] * 0.25 + Vector3::splat(0.5))
+    }
+
+    pub fn normal_buf(&self) -> Vec<u32> {
+        self.debug_buf(|i| self.normal[i] * 0.5 + Vector3::splat(0.5))
+    }
+
+    pub fn albedo_buf(&self) -> Vec<u32> {
+        self.debug_buf(|i| self.albedo[i])
+    }
+
+    //  a distinct hue per material id
+    pub fn material_buf(&self) -> Vec<u32> {
+        self.debug_buf(|i| {
+            let h = self.material[i].wrapping_add(1).wrapping_mul(0x9e3779b9);
+            Vector3::new((h >> 24) as f32, ((h >> 16) & 0xff) as f32, ((h >> 8) & 0xff) as f32) / 255.0
+        })
+    }
+
+    //  [0, 1] color of each covered pixel, black elsewhere
+    fn debug_buf(&self, color: impl Fn(usize) -> Vector3) -> Vec<u32> {
+        (0..self.material.len()).map(|i| {
+            let c = if self.covered(i) { color(i).clamp(Vector3::ZERO, Vector3::ONE) } else { Vector3::ZERO };
+            color_from_vec4((c * 255.0).extend(255.0))
+        }).collect()
+    }
+}
+
+/// Geometry pass of deferred shading: writes the normal mapped surface of
+/// the uniforms' material to the G-buffer, and its unlit albedo to the frame
+pub struct GBufferShader<'a> {
+    pub uniforms: Uniforms<'a>,
+    pub material_id: u32,
+}
+
+impl Shader for GBufferShader<'_> {
+    type Attribute = Vertex;
+    type Varying = PhongVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, PhongVarying) {
+        phong_vertex(&self.uniforms, attribute)
+    }
+
+    fn fragment(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> bool {
+        *color = color_from_vec4(self.uniforms.albedo(frag.varying.uv).extend(255.0));
+
+        false
+    }
+
+    fn surface(&self, frag: &Fragment<PhongVarying>) -> Option<Surface> {
+        let v = &frag.varying;
+        let surface = self.uniforms.surface(v.position, self.uniforms.mapped_normal(v), v.uv);
+        Some(Surface { material: self.material_id, ..surface })
+    }
+
+    //  the albedo is already in the surface
+    fn fragment_surface(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> (bool, Option<Surface>) {
+        let surface = self.surface(frag);
+        if let Some(surface) = &surface {
+            *color = color_from_vec4((surface.albedo * 255.0).extend(255.0));
+        }
+        (false, surface)
+    }
+}
+
+impl Renderer {
+    /// Lighting pass of deferred shading: shade every covered pixel of the
+    /// G-buffer with the uniforms' lights and environment.  Occlusion and
+    /// emissive textures aren't available in this pass.
+    pub fn shade_gbuffer(&mut self, uniforms: &Uniforms, exposure: f32) {
+        let gbuffer = &self.gbuffer;
+        for (i, pixel) in self.buf.iter_mut().enumerate() {
+            if !gbuffer.covered(i) {
+                continue;
+            }
+            let lit = uniforms.cook_torrance(
+                gbuffer.position[i],
+                gbuffer.normal[i],
+                srgb_to_linear(gbuffer.albedo[i]),
+                gbuffer.metallic[i],
+                gbuffer.roughness[i],
+                1.0,
+            );
+            *pixel = uniforms.fogged(gbuffer.position[i], color_from_linear(lit, exposure));
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+use crate::geometry::Vector3;
+use crate::renderer::Texture;
+use crate::util::{Shared, read_png_texture, srgb_to_linear, vec3_gl_from_color};
+
+//  width of the map the diffuse and specular prefiltering integrate over
+const PREFILTER_WIDTH: usize = 64;
+//  number of specular levels, level i is prefiltered for roughness i / (SPECULAR_LEVELS - 1)
+const SPECULAR_LEVELS: usize = 5;
+
+/// Linear radiance stored as an equirectangular (latitude-longitude) image,
+/// row 0 looks up +y and the centre column looks down -z
+#[derive(Clone)]
+pub struct EquirectMap {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<Vector3>,
+}
+
+impl EquirectMap {
+    //  decodes the sRGB texture to linear radiance
+    pub fn from_texture(texture: &Texture) -> Self {
+        Self {
+            width: texture.width as usize,
+            height: texture.height as usize,
+            texels: texture.buf.iter().map(|c| srgb_to_linear(vec3_gl_from_color(*c))).collect(),
+        }
+    }
+
+    /// Read a Radiance RGBE (.hdr) image, whose radiance isn't clipped to
+    /// [0, 1] as an 8-bit texture's is.  Only the usual top to bottom
+    /// orientation and run-length encoding are supported.
+    pub fn read_hdr(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let (width, height, pixels) = parse_hdr(&bytes)?;
+        let mut texels = Vec::with_capacity(width * height);
+        let mut rest = pixels;
+        for _ in 0..height {
+            let (scanline, next) = read_scanline(rest, width)?;
+            texels.extend(scanline.chunks_exact(4).map(rgbe_to_linear));
+            rest = next;
+        }
+        Ok(Self { width, height, texels })
+    }
+
+    pub fn from_fn(width: usize, height: usize, radiance: impl Fn(Vector3) -> Vector3) -> Self {
+        let mut map = Self { width, height, texels: vec![] };
+        map.texels = (0..width * height).map(|i| radiance(map.direction(i % width, i / width))).collect();
+        map
+    }
+
+    //  unit direction through the centre of a texel
+    pub fn direction(&self, x: usize, y: usize) -> Vector3 {
+        let theta = (y as f32 + 0.5) / self.height as f32 * PI;
+        let phi = ((x as f32 + 0.5) / self.width as f32 - 0.5) * 2.0 * PI;
+        Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+
+    //  solid angle subtended by a texel of row y
+    fn solid_angle(&self, y: usize) -> f32 {
+        let theta = (y as f32 + 0.5) / self.height as f32 * PI;
+        (2.0 * PI / self.width as f32) * (PI / self.height as f32) * theta.sin()
+    }
+
+    fn texel(&self, x: usize, y: usize) -> Vector3 {
+        self.texels[y * self.width + x]
+    }
+
+    /// Bilinear sample of the radiance arriving from a direction
+    pub fn sample(&self, dir: Vector3) -> Vector3 {
+        let d = dir.normalize_or_zero();
+        let u = 0.5 + f32::atan2(d.x, -d.z) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (sx, sy) = (x - x.floor(), y - y.floor());
+        //  wrap around horizontally, clamp at the poles
+        let x0 = (x.floor() as i64).rem_euclid(self.width as i64) as usize;
+        let x1 = (x0 + 1) % self.width;
+        let y0 = y.floor() as usize;
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let top = Vector3::lerp(self.texel(x0, y0), self.texel(x1, y0), sx);
+        let bottom = Vector3::lerp(self.texel(x0, y1), self.texel(x1, y1), sx);
+        Vector3::lerp(top, bottom, sy)
+    }
+
+    //  2x2 box filter, halving the resolution
+    fn downsample(&self) -> Self {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let texels = (0..width * height).map(|i| {
+            let (x, y) = ((i % width) * 2, (i / width) * 2);
+            let (x1, y1) = ((x + 1).min(self.width - 1), (y + 1).min(self.height - 1));
+            (self.texel(x, y) + self.texel(x1, y) + self.texel(x, y1) + self.texel(x1, y1)) * 0.25
+        }).collect();
+        Self { width, height, texels }
+    }
+
+    //  halve the resolution until no wider than width
+    fn reduced(&self, width: usize) -> Self {
+        let mut map = self.clone();
+        while map.width > width {
+            map = map.downsample();
+        }
+        map
+    }
+
+    //  convolve with a normalized cosine power lobe, approximating the GGX
+    //  specular lobe of the given roughness around the reflection vector
+    fn prefilter(&self, width: usize, roughness: f32) -> Self {
+        let alpha = (roughness * roughness).max(1.0e-3);
+        let power = ((2.0 / (alpha * alpha) - 2.0) / 4.0).max(1.0);
+
+        Self::from_fn(width, width / 2, |n| {
+            let mut sum = Vector3::ZERO;
+            let mut weight = 0.0;
+            for y in 0..self.height {
+                let solid_angle = self.solid_angle(y);
+                for x in 0..self.width {
+                    let cos = Vector3::dot(n, self.direction(x, y));
+                    if cos > 0.0 {
+                        let w = cos.powf(power) * solid_angle;
+                        sum += self.texel(x, y) * w;
+                        weight += w;
+                    }
+                }
+            }
+            if weight > 0.0 { sum / weight } else { self.sample(n) }
+        })
+    }
+
+    //  project onto the real spherical harmonics of bands 0 to 2
+    fn project_sh(&self) -> [Vector3; 9] {
+        let mut sh = [Vector3::ZERO; 9];
+        for y in 0..self.height {
+            let solid_angle = self.solid_angle(y);
+            for x in 0..self.width {
+                let radiance = self.texel(x, y) * solid_angle;
+                for (c, basis) in sh.iter_mut().zip(sh_basis(self.direction(x, y))) {
+                    *c += radiance * basis;
+                }
+            }
+        }
+        sh
+    }
+}
+
+fn sh_basis(d: Vector3) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3.0 * d.z * d.z - 1.0),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+/// Environment lighting prefiltered for image-based lighting: spherical
+/// harmonic irradiance for diffuse, and specular levels by roughness
+#[derive(Clone, PartialEq)]
+pub struct Environment {
+    pub radiance: Shared<EquirectMap>,
+    pub intensity: f32,
+    //  file the radiance was loaded from
+    pub path: Option<String>,
+    specular: Shared<Vec<EquirectMap>>,
+    sh: [Vector3; 9],
+}
+
+impl Environment {
+    pub fn new(radiance: EquirectMap) -> Self {
+        let base = radiance.reduced(PREFILTER_WIDTH);
+        //  level 0 is mirror-like and samples the radiance directly
+        let specular = (1..SPECULAR_LEVELS).map(|level| {
+            let roughness = level as f32 / (SPECULAR_LEVELS - 1) as f32;
+            base.prefilter((PREFILTER_WIDTH >> (level - 1)).max(16), roughness)
+        }).collect::<Vec<_>>();
+        let sh = base.project_sh();
+
+        Self {
+            radiance: radiance.into(),
+            intensity: 1.0,
+            path: None,
+            specular: specular.into(),
+            sh,
+        }
+    }
+
+    pub fn from_texture(texture: &Texture) -> Self {
+        Self {
+            path: texture.path.clone(),
+            ..Self::new(EquirectMap::from_texture(texture))
+        }
+    }
+
+    /// Load a Radiance .hdr image, or an 8-bit sRGB PNG whose lighting is
+    /// clipped at white
+    pub fn load(path: &str) -> Result<Self, String> {
+        let hdr = matches!(Path::new(path).extension().and_then(|e| e.to_str()), Some(e) if e.eq_ignore_ascii_case("hdr"));
+        if hdr {
+            Ok(Self { path: Some(path.to_string()), ..Self::new(EquirectMap::read_hdr(path)?) })
+        } else {
+            let texture = read_png_texture(path).map_err(|e| e.to_string())?;
+            Ok(Self::from_texture(&texture))
+        }
+    }
+
+    /// Irradiance arriving at a surface with normal n, by Ramamoorthi and
+    /// Hanrahan's convolution of the harmonics with the clamped cosine
+    pub fn irradiance(&self, n: Vector3) -> Vector3 {
+        const BAND: [f32; 9] = [PI, 2.0 * PI / 3.0, 2.0 * PI / 3.0, 2.0 * PI / 3.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0];
+        let e = self.sh.iter().zip(sh_basis(n)).zip(BAND)
+            .map(|((c, basis), a)| *c * basis * a)
+            .fold(Vector3::ZERO, |l, r| l + r);
+        e.max(Vector3::ZERO) * self.intensity
+    }
+
+    /// Radiance around reflection direction r prefiltered for roughness
+    pub fn specular(&self, r: Vector3, roughness: f32) -> Vector3 {
+        let level = roughness.clamp(0.0, 1.0) * (SPECULAR_LEVELS - 1) as f32;
+        let (lo, t) = (level.floor() as usize, level.fract());
+        let sample = |i: usize| if i == 0 { self.radiance.sample(r) } else { self.specular[i - 1].sample(r) };
+
+        let c = if lo + 1 < SPECULAR_LEVELS {
+            Vector3::lerp(sample(lo), sample(lo + 1), t)
+        } else {
+            sample(lo)
+        };
+        c * self.intensity
+    }
+
+    /// Unfiltered radiance seen looking along dir
+    pub fn background(&self, dir: Vector3) -> Vector3 {
+        self.radiance.sample(dir) * self.intensity
+    }
+}
+
+//  the size and pixel data of a Radiance file after its header
+fn parse_hdr(bytes: &[u8]) -> Result<(usize, usize, &[u8]), String> {
+    let mut rest = bytes;
+    let mut next_line = || -> Result<String, String> {
+        let end = rest.iter().position(|b| *b == b'\n').ok_or("truncated header")?;
+        let line = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+        rest = &rest[end + 1..];
+        Ok(line)
+    };
+
+    if !next_line()?.starts_with("#?") {
+        return Err("not a Radiance file".to_string());
+    }
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported format '{}'", format));
+            }
+        }
+    }
+    let resolution = next_line()?;
+    let size = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => width.parse().ok().zip(height.parse().ok()),
+        _ => None,
+    };
+    let (width, height) = size.ok_or_else(|| format!("unsupported resolution '{}'", resolution))?;
+    if width == 0 || height == 0 {
+        return Err(format!("empty image '{}'", resolution));
+    }
+    Ok((width, height, rest))
+}
+
+//  one scanline of RGBE pixels, flat or run-length encoded per channel
+fn read_scanline(bytes: &[u8], width: usize) -> Result<(Vec<u8>, &[u8]), String> {
+    let truncated = || "truncated pixel data".to_string();
+    let encoded = (8..0x8000).contains(&width)
+        && matches!(bytes, [2, 2, hi, lo, ..] if (*hi as usize) << 8 | *lo as usize == width);
+    if !encoded {
+        let len = width * 4;
+        return if bytes.len() < len { Err(truncated()) } else { Ok((bytes[..len].to_vec(), &bytes[len..])) };
+    }
+
+    let mut scanline = vec![0; width * 4];
+    let mut i = 4;
+    let mut byte = || -> Result<u8, String> {
+        let b = *bytes.get(i).ok_or_else(truncated)?;
+        i += 1;
+        Ok(b)
+    };
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = byte()? as usize;
+            //  a count above 128 repeats the next byte, otherwise that many bytes follow
+            let (len, run) = if count > 128 { (count - 128, true) } else { (count, false) };
+            if len == 0 || x + len > width {
+                return Err("bad run length".to_string());
+            }
+            let value = if run { byte()? } else { 0 };
+            for _ in 0..len {
+                scanline[x * 4 + channel] = if run { value } else { byte()? };
+                x += 1;
+            }
+        }
+    }
+    Ok((scanline, &bytes[i..]))
+}
+
+fn rgbe_to_linear(rgbe: &[u8]) -> Vector3 {
+    if rgbe[3] == 0 {
+        return Vector3::ZERO;
+    }
+    let scale = 2.0f32.powi(rgbe[3] as i32 - (128 + 8));
+    Vector3::new(rgbe[0] as f32, rgbe[1] as f32, rgbe[2] as f32) * scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n";
+
+    fn with_resolution(resolution: &str) -> Vec<u8> {
+        [HEADER, resolution.as_bytes(), b"\n\x01\x02"].concat()
+    }
+
+    #[test]
+    fn parses_the_resolution() {
+        let bytes = with_resolution("-Y 2 +X 3");
+        assert_eq!(parse_hdr(&bytes), Ok((3, 2, &[1u8, 2][..])));
+    }
+
+    #[test]
+    fn rejects_an_empty_image() {
+        assert!(parse_hdr(&with_resolution("-Y 0 +X 3")).is_err());
+        assert!(parse_hdr(&with_resolution("-Y 2 +X 0")).is_err());
+    }
+
+    #[test]
+    fn reads_a_flat_scanline() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let (scanline, rest) = read_scanline(&bytes, 2).unwrap();
+        assert_eq!(scanline, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(rest, [9]);
+        assert!(read_scanline(&bytes[..7], 2).is_err());
+    }
+
+    #[test]
+    fn reads_a_run_length_encoded_scanline() {
+        //  red as 3 literal bytes then a run of 5, the other channels as runs of 8
+        let bytes = [2, 2, 0, 8, 3, 10, 11, 12, 128 + 5, 13, 128 + 8, 20, 128 + 8, 30, 128 + 8, 128, 99];
+        let (scanline, rest) = read_scanline(&bytes, 8).unwrap();
+        let red: Vec<u8> = scanline.chunks_exact(4).map(|rgbe| rgbe[0]).collect();
+        assert_eq!(red, [10, 11, 12, 13, 13, 13, 13, 13]);
+        assert!(scanline.chunks_exact(4).all(|rgbe| rgbe[1..] == [20, 30, 128]));
+        assert_eq!(rest, [99]);
+    }
+
+    #[test]
+    fn rejects_a_run_past_the_scanline() {
+        let bytes = [2, 2, 0, 8, 128 + 9, 10];
+        assert!(read_scanline(&bytes, 8).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use egui::CtxRef;
+
+/// A window listing a directory to pick a file with one of a set of
+/// extensions.  Chosen files under the working directory are given relative
+/// to it, so saved scenes stay portable.
+pub struct FileBrowser {
+    pub open: bool,
+    title: String,
+    dir: PathBuf,
+    extensions: &'static [&'static str],
+    selected: Option<PathBuf>,
+    /// Shown under the listing, e.g. why the chosen file couldn't be loaded
+    pub error: Option<String>,
+}
+
+impl FileBrowser {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            title: String::new(),
+            dir: env::current_dir().unwrap_or_default(),
+            extensions: &[],
+            selected: None,
+            error: None,
+        }
+    }
+
+    /// Show the browser titled `title` listing files with the extensions
+    pub fn browse(&mut self, title: &str, extensions: &'static [&'static str]) {
+        self.open = true;
+        self.title = title.to_string();
+        self.extensions = extensions;
+        self.selected = None;
+        self.error = None;
+    }
+
+    /// Draw the browser while open, returns the file chosen this frame
+    pub fn show(&mut self, ctx: &CtxRef) -> Option<String> {
+        let mut open = self.open;
+        let mut chosen = None;
+        let mut close = false;
+
+        egui::Window::new("Open file")
+            .id(egui::Id::new("file_browser"))
+            .open(&mut open)
+            .default_size((400.0, 400.0))
+            .show(ctx, |ui| {
+                ui.label(&self.title);
+                ui.label(self.dir.display().to_string());
+                ui.separator();
+
+                let (dirs, files) = match self.entries() {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        self.error = Some(format!("{}: {}", self.dir.display(), e));
+                        (vec![], vec![])
+                    }
+                };
+                let mut enter = None;
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    if let Some(parent) = self.dir.parent() {
+                        if ui.selectable_label(false, "..").clicked() {
+                            enter = Some(parent.to_path_buf());
+                        }
+                    }
+                    for dir in dirs {
+                        if ui.selectable_label(false, format!("{}/", file_name(&dir))).clicked() {
+                            enter = Some(dir);
+                        }
+                    }
+                    for file in files {
+                        let response = ui.selectable_label(self.selected.as_ref() == Some(&file), file_name(&file));
+                        if response.double_clicked() {
+                            chosen = Some(file.clone());
+                        }
+                        if response.clicked() {
+                            self.selected = Some(file);
+                        }
+                    }
+                });
+                if let Some(dir) = enter {
+                    self.dir = dir;
+                    self.selected = None;
+                    self.error = None;
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(self.selected.is_some(), egui::Button::new("Open")).clicked() {
+                        chosen = self.selected.clone();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close = true;
+                    }
+                });
+                if let Some(e) = &self.error {
+                    ui.colored_label(egui::Color32::RED, e.as_str());
+                }
+            });
+
+        self.open = open && !close;
+        chosen.map(|path| relative_to_cwd(&path))
+    }
+
+    //  subdirectories and files with a listed extension, sorted by name
+    fn entries(&self) -> std::io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let (mut dirs, mut files) = (vec![], vec![]);
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if matches!(path.extension().and_then(|e| e.to_str()),
+                Some(e) if self.extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+            {
+                files.push(path);
+            }
+        }
+        dirs.sort();
+        files.sort();
+        Ok((dirs, files))
+    }
+}
+
+impl Default for FileBrowser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn relative_to_cwd(path: &Path) -> String {
+    let relative = env::current_dir().ok().and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf));
+    relative.as_deref().unwrap_or(path).to_string_lossy().into_owned()
+}
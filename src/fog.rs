@@ -0,0 +1,164 @@
+use crate::geometry::Vector3;
+use crate::renderer::{Camera, Renderer, unproject};
+use crate::util::{color_from_vec4, vec4_from_color};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FogMode {
+    /// Fades in between the start and end distances
+    Linear,
+    Exponential,
+    ExponentialSquared,
+    /// Exponential fog whose density falls off exponentially above `height`
+    Height,
+}
+
+impl FogMode {
+    pub const ALL: [FogMode; 4] = [FogMode::Linear, FogMode::Exponential, FogMode::ExponentialSquared, FogMode::Height];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FogMode::Linear => "Linear",
+            FogMode::Exponential => "Exponential",
+            FogMode::ExponentialSquared => "Exponential squared",
+            FogMode::Height => "Height",
+        }
+    }
+}
+
+/// Fog blending surfaces towards `color` (sRGB in [0, 1]) with distance from
+/// the eye, either per fragment in the built-in shaders or as a pass over
+/// the depth buffer after shading.  The background isn't fogged.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub enabled: bool,
+    pub mode: FogMode,
+    pub per_fragment: bool,
+    pub color: Vector3,
+    pub density: f32,
+    //  distances of linear fog
+    pub start: f32,
+    pub end: f32,
+    //  height fog is at full density below height
+    pub height: f32,
+    pub falloff: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: FogMode::Exponential,
+            per_fragment: true,
+            color: Vector3::new(0.6, 0.65, 0.7),
+            density: 0.3,
+            start: 2.0,
+            end: 6.0,
+            height: 0.0,
+            falloff: 1.0,
+        }
+    }
+}
+
+impl Fog {
+    /// How much of the fog color, in [0, 1], covers a world position seen from the eye
+    pub fn factor(&self, eye: Vector3, position: Vector3) -> f32 {
+        let d = (position - eye).length();
+        let f = match self.mode {
+            FogMode::Linear => (d - self.start) / (self.end - self.start).max(f32::EPSILON),
+            FogMode::Exponential => 1.0 - (-self.density * d).exp(),
+            FogMode::ExponentialSquared => 1.0 - (-(self.density * d).powi(2)).exp(),
+            FogMode::Height => 1.0 - (-self.optical_depth(eye.y, position.y, d)).exp(),
+        };
+        f.clamp(0.0, 1.0)
+    }
+
+    //  height fog density integrated along a ray of length d from height y0
+    //  to y1, split where it crosses `height` into its constant density part
+    //  below and its exponential part above
+    fn optical_depth(&self, y0: f32, y1: f32, d: f32) -> f32 {
+        let (low, high) = (y0.min(y1), y0.max(y1));
+        if high <= self.height {
+            return self.density * d;
+        }
+        let (below, start) = if low < self.height {
+            let t = (self.height - low) / (high - low);
+            (d * t, self.height)
+        } else {
+            (0.0, low)
+        };
+        //  the exponential part from `start` up to `high`
+        let length = d - below;
+        let k = self.falloff * (high - start);
+        let start_density = self.density * (-self.falloff * (start - self.height)).exp();
+        let above = if k.abs() > 1.0e-4 {
+            start_density * length * (1.0 - (-k).exp()) / k
+        } else {
+            start_density * length
+        };
+        self.density * below + above
+    }
+
+    //  blend an opaque color towards the fog color
+    pub fn apply(&self, eye: Vector3, position: Vector3, color: u32) -> u32 {
+        let c = vec4_from_color(color);
+        let fogged = Vector3::lerp(c.truncate(), self.color * 255.0, self.factor(eye, position));
+        color_from_vec4(fogged.extend(c.w))
+    }
+}
+
+impl Renderer {
+    /// Fog the drawn pixels with positions reconstructed from the zbuf
+    pub fn apply_fog(&mut self, fog: &Fog, camera: &Camera) {
+        let screen_to_world = self.screen_to_world(camera);
+        let eye = camera.position();
+        let (width, height) = (self.width, self.height);
+        for (i, (pixel, z)) in self.buf.iter_mut().zip(self.zbuf.iter()).enumerate() {
+            if *z == 0.0 {
+                continue;
+            }
+            let (x, y) = (i as i32 % width, height - 1 - i as i32 / width);
+            *pixel = fog.apply(eye, unproject(screen_to_world, x, y, *z), *pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Index, Material, Mesh, Object, RendererState};
+    use crate::shader::ShaderKind;
+
+    //  a triangle tilted away from the camera, drawn with fog
+    fn draw_fogged(per_fragment: bool) -> Vec<u32> {
+        let mut mesh = Mesh::new();
+        mesh.vs.extend([Vector3::new(-1.0, -1.0, 0.5), Vector3::new(1.0, -1.0, -0.5), Vector3::new(0.0, 1.0, 0.0)]);
+        mesh.indexes.extend((1..4).map(|v| Index::new(v, 0, 0)));
+
+        let mut state = RendererState { shader: ShaderKind::Flat, ..RendererState::default() };
+        state.objects.push(Object::new(mesh, Material::default()));
+        state.fog = Fog {
+            enabled: true,
+            mode: FogMode::Linear,
+            per_fragment,
+            color: Vector3::ONE,
+            start: 2.0,
+            end: 5.0,
+            ..Fog::default()
+        };
+        let mut renderer = Renderer::new(64, 64);
+        renderer.draw_mesh_shader(&state);
+        renderer.buf
+    }
+
+    #[test]
+    fn post_pass_fog_matches_per_fragment_fog() {
+        let (per_fragment, post_pass) = (draw_fogged(true), draw_fogged(false));
+        let mut fogged = 0;
+        for (a, b) in per_fragment.iter().zip(&post_pass) {
+            let (a, b) = (vec4_from_color(*a), vec4_from_color(*b));
+            assert!((a - b).abs().max_element() <= 1.0, "per fragment {} post pass {}", a, b);
+            fogged += (a.x > 0.0) as usize;
+        }
+        assert!(fogged > 100);
+    }
+}
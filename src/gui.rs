@@ -1,9 +1,16 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
 use egui::{ClippedMesh, CtxRef, Ui};
 use egui_wgpu_backend::{BackendError, RenderPass, ScreenDescriptor};
+use log::{info, warn};
 use pixels::{wgpu, PixelsContext};
 use winit::window::Window;
 
-use crate::{geometry::Vector3, renderer::{RendererState, DisplayBuffer}, objloader::load_obj, util::load_png_texture};
+use crate::{geometry::Vector3, renderer::{RendererState, DisplayBuffer, BackgroundMode, Material, NormalSpace, Object}, file_browser::FileBrowser, log_console::LogConsole, objloader::read_obj, shader::ShaderKind, light::{Attenuation, Light}, environment::Environment, fog::FogMode, postprocess::{Bokeh, Effect, Lut, PostEffect}, scene::{self, Scene}, stats::{RenderStats, Stage}, util::read_png_texture};
+
+/// File the viewer's state is saved to on exit and restored from on start
+pub const SESSION_PATH: &str = "session.scene";
 
 /// Manages all state required for rendering egui over `Pixels`.
 pub struct Framework {
@@ -18,16 +25,105 @@ pub struct Framework {
     pub gui: Gui,
 }
 
+//  file operations requested from the UI, done once it no longer borrows the state
+enum SceneAction {
+    LoadScene,
+    SaveScene,
+    LoadSession,
+    SaveSession,
+}
+
+//  what a file chosen in the file browser is loaded as
+#[derive(Clone, Copy)]
+enum FileTarget {
+    //  the selected object's mesh, or a new object when there are none
+    Mesh,
+    Object,
+    Diffuse,
+    Normal,
+    MetallicRoughness,
+    Occlusion,
+    Emissive,
+}
+
+impl FileTarget {
+    const ALL: [FileTarget; 7] = [
+        FileTarget::Mesh,
+        FileTarget::Object,
+        FileTarget::Diffuse,
+        FileTarget::Normal,
+        FileTarget::MetallicRoughness,
+        FileTarget::Occlusion,
+        FileTarget::Emissive,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            FileTarget::Mesh => "Open mesh",
+            FileTarget::Object => "Add object",
+            FileTarget::Diffuse => "Open diffuse texture",
+            FileTarget::Normal => "Open normal map",
+            FileTarget::MetallicRoughness => "Open metallic-roughness texture",
+            FileTarget::Occlusion => "Open occlusion texture",
+            FileTarget::Emissive => "Open emissive texture",
+        }
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            FileTarget::Mesh | FileTarget::Object => &["obj"],
+            _ => &["png"],
+        }
+    }
+
+    //  whether it changes the selected object
+    fn needs_object(&self) -> bool {
+        !matches!(self, FileTarget::Mesh | FileTarget::Object)
+    }
+}
+
 pub struct Gui {
     /// Only show the egui window when true.
     window_open: bool,
+    //  scene file to load or save, the image size it gives is kept when saving
+    scene_path: String,
+    scene_size: Option<(i32, i32)>,
+    scene_error: Option<String>,
+    //  index of the object the renderer window edits
+    object: usize,
+    lights_open: bool,
+    post_open: bool,
+    stats_open: bool,
+    //  equirectangular environment image to load
+    environment_path: String,
+    environment_error: Option<String>,
+    //  color grading lookup table image to load
+    lut_path: String,
+    lut_error: Option<String>,
+    /// Set while waiting for a click on the frame to focus depth of field at
+    pub pick_focus: bool,
+    /// Drags turn the camera in place and WASD moves it, instead of orbiting
+    /// the center
+    pub fly_mode: bool,
+    /// Frame pixels per window pixel, the frame is scaled up to fill the window
+    pub render_scale: f32,
+    /// Statistics of the last finished frame and how many have finished
+    pub stats: RenderStats,
+    pub render_count: u32,
+    file_browser: FileBrowser,
+    file_target: FileTarget,
+    //  shared with the logger, see `log_console::init`
+    log_console: Arc<Mutex<LogConsole>>,
+    /// Set when the state may have changed, it is drawn again if it differs
+    /// from the last frame's
+    pub redraw: bool,
     
     pub renderer_state: RendererState,
 }
 
 impl Framework {
     /// Create egui.
-    pub fn new(width: u32, height: u32, scale_factor: f32, pixels: &pixels::Pixels) -> Self {
+    pub fn new(width: u32, height: u32, scale_factor: f32, pixels: &pixels::Pixels, scene_path: Option<&str>, log_console: Arc<Mutex<LogConsole>>) -> Self {
         let egui_ctx = CtxRef::default();
         let egui_state = egui_winit::State::from_pixels_per_point(scale_factor);
         let screen_descriptor = ScreenDescriptor {
@@ -36,7 +132,7 @@ impl Framework {
             scale_factor,
         };
         let rpass = RenderPass::new(pixels.device(), pixels.render_texture_format(), 1);
-        let gui = Gui::new();
+        let gui = Gui::new(scene_path, log_console);
 
         Self {
             egui_ctx,
@@ -48,6 +144,16 @@ impl Framework {
         }
     }
 
+    /// Whether egui is using the pointer, so clicks shouldn't reach the frame.
+    pub fn wants_pointer_input(&self) -> bool {
+        self.egui_ctx.wants_pointer_input()
+    }
+
+    /// Whether egui has keyboard focus, so keys shouldn't move the camera.
+    pub fn wants_keyboard_input(&self) -> bool {
+        self.egui_ctx.wants_keyboard_input()
+    }
+
     /// Handle input events from the window manager.
     pub fn handle_event(&mut self, event: &winit::event::WindowEvent) -> bool {
         self.egui_state.on_event(&self.egui_ctx, event)
@@ -113,44 +219,159 @@ impl Framework {
 }
 
 impl Gui {
-    /// Create a `Gui`.
-    fn new() -> Self {
-        Self {
+    /// Create a `Gui` showing the scene at `scene_path`, or the last session
+    /// when none is given.
+    fn new(scene_path: Option<&str>, log_console: Arc<Mutex<LogConsole>>) -> Self {
+        let mut gui = Self {
             window_open: true,
-            renderer_state: RendererState{
-                display_buffer: DisplayBuffer::Frame,
-                mesh: load_obj("obj/african_head.obj"),
-                diffuse: load_png_texture("obj/african_head_diffuse.png"),
-                normal: load_png_texture("obj/african_head_nm_tangent.png"),
-                model: Vector3::ZERO,
-                eye: Vector3::new(1.0, 1.0, 3.0),
-                center: Vector3::ZERO,
-                up: Vector3::new(0.0, 1.0, 0.0),
-                light_dir: Vector3::new(1.0, 1.0, 1.0),
-                rotation: Vector3::ZERO,
+            scene_path: scene_path.unwrap_or(scene::DEFAULT_SCENE).to_string(),
+            scene_size: None,
+            scene_error: None,
+            object: 0,
+            lights_open: false,
+            post_open: false,
+            stats_open: false,
+            environment_path: String::new(),
+            environment_error: None,
+            lut_path: String::new(),
+            lut_error: None,
+            pick_focus: false,
+            fly_mode: false,
+            render_scale: 1.0,
+            stats: RenderStats::default(),
+            render_count: 0,
+            file_browser: FileBrowser::new(),
+            file_target: FileTarget::Mesh,
+            log_console,
+            redraw: false,
+            renderer_state: RendererState::default(),
+        };
+        if scene_path.is_some() || !Path::new(SESSION_PATH).exists() {
+            gui.load_scene();
+        } else if let Err(e) = gui.load_session() {
+            gui.load_scene();
+            gui.scene_error = Some(e);
+        }
+        gui
+    }
+
+    //  replace the renderer state with the scene at `scene_path`
+    fn load_scene(&mut self) {
+        match scene::load_scene(&self.scene_path) {
+            Ok(scene) => {
+                info!("loaded scene {}", self.scene_path);
+                self.set_scene(scene);
+            }
+            Err(e) => {
+                warn!("loading scene {} failed: {}", self.scene_path, e);
+                self.scene_error = Some(e);
             }
         }
     }
 
+    fn save_scene(&mut self) {
+        self.scene_error = scene::save_scene(&self.scene_path, &self.renderer_state, self.scene_size).err();
+        match &self.scene_error {
+            None => info!("saved scene {}", self.scene_path),
+            Some(e) => warn!("saving scene {} failed: {}", self.scene_path, e),
+        }
+    }
+
+    fn set_scene(&mut self, scene: Scene) {
+        self.renderer_state = scene.state;
+        self.scene_size = scene.size;
+        self.object = 0;
+        self.scene_error = None;
+        self.redraw = true;
+    }
+
+    /// Restore the state saved by `save_session`
+    pub fn load_session(&mut self) -> Result<(), String> {
+        let scene = scene::load_scene(SESSION_PATH)?;
+        self.set_scene(scene);
+        info!("restored the session from {}", SESSION_PATH);
+        Ok(())
+    }
+
+    /// Save the renderer state, with the paths of its assets, to `SESSION_PATH`
+    pub fn save_session(&self) -> Result<(), String> {
+        scene::save_scene(SESSION_PATH, &self.renderer_state, self.scene_size)?;
+        info!("saved the session to {}", SESSION_PATH);
+        Ok(())
+    }
+
     /// Create the UI using egui.
     fn ui(&mut self, ctx: &CtxRef) {
         let RendererState {
-            model,
-            eye,
-            center,
-            up,
-            light_dir,
-            rotation,
+            objects,
+            cameras,
+            active_camera,
+            lights,
             display_buffer,
+            shader,
+            deferred,
+            environment,
+            background,
+            ssao,
+            fog,
+            post_effects,
             ..
         } = &mut self.renderer_state;
+        let Gui {
+            scene_path,
+            scene_error,
+            object,
+            environment_path,
+            environment_error,
+            lut_path,
+            lut_error,
+            pick_focus,
+            fly_mode,
+            render_scale,
+            stats,
+            render_count,
+            ..
+        } = self;
+
+        let mut scene_action = None;
+        let mut browse = None;
 
         egui::TopBottomPanel::top("menubar_container").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
+                egui::menu::menu(ui, "File", |ui| {
+                    for target in FileTarget::ALL {
+                        let enabled = !target.needs_object() || !objects.is_empty();
+                        if ui.add_enabled(enabled, egui::Button::new(format!("{}...", target.name()))).clicked() {
+                            browse = Some(target);
+                        }
+                    }
+                });
                 egui::menu::menu(ui, "Controls", |ui| {
                     if ui.button("Renderer").clicked() {
                         self.window_open = true;
                     }
+                    if ui.button("Lights").clicked() {
+                        self.lights_open = true;
+                    }
+                    if ui.button("Post-processing").clicked() {
+                        self.post_open = true;
+                    }
+                    if ui.button("Render stats").clicked() {
+                        self.stats_open = true;
+                    }
+                    if ui.button("Log").clicked() {
+                        if let Ok(mut console) = self.log_console.lock() {
+                            console.open = true;
+                        }
+                    }
+                });
+                egui::menu::menu(ui, "Session", |ui| {
+                    if ui.button("Save session").clicked() {
+                        scene_action = Some(SceneAction::SaveSession);
+                    }
+                    if ui.button("Load session").clicked() {
+                        scene_action = Some(SceneAction::LoadSession);
+                    }
                 })
             });
         });
@@ -159,25 +380,590 @@ impl Gui {
             .open(&mut self.window_open)
             .show(ctx, |ui| {
                 egui::Grid::new("renderer").show(ui, |ui| {
-                    drag_vec3_row(ui, "Model", model);
-                    drag_vec3_row(ui, "Eye", eye);
-                    drag_vec3_row(ui, "Center", center);
-                    drag_vec3_row(ui, "Up", up);
-                    drag_vec3_row(ui, "Light dir", light_dir);
-                    drag_vec3_row(ui, "Rotation", rotation);
+                    ui.label("Scene");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(scene_path);
+                        if ui.button("Load").clicked() {
+                            scene_action = Some(SceneAction::LoadScene);
+                        }
+                        if ui.button("Save").clicked() {
+                            scene_action = Some(SceneAction::SaveScene);
+                        }
+                    });
+                    ui.end_row();
+
+                    if let Some(e) = scene_error {
+                        ui.label("");
+                        ui.colored_label(egui::Color32::RED, e.as_str());
+                        ui.end_row();
+                    }
+
+                    ui.label("Camera");
+                    egui::ComboBox::from_id_source("camera")
+                        .selected_text(cameras[*active_camera].name.as_str())
+                        .show_ui(ui, |ui| {
+                            for (i, camera) in cameras.iter().enumerate() {
+                                ui.selectable_value(active_camera, i, camera.name.as_str());
+                            }
+                        });
+                    ui.end_row();
+
+                    let camera = &mut cameras[*active_camera];
+                    drag_vec3_row(ui, "Eye", &mut camera.eye);
+                    drag_vec3_row(ui, "Center", &mut camera.center);
+                    drag_vec3_row(ui, "Up", &mut camera.up);
+
+                    ui.label("Navigation");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(fly_mode, false, "Orbit");
+                        ui.radio_value(fly_mode, true, "Fly (F)");
+                    });
+                    ui.end_row();
+
+                    if let Some(selected) = objects.get(*object) {
+                        ui.label("Object");
+                        egui::ComboBox::from_id_source("object")
+                            .selected_text(object_label(selected, *object))
+                            .show_ui(ui, |ui| {
+                                for (i, o) in objects.iter().enumerate() {
+                                    ui.selectable_value(object, i, object_label(o, i));
+                                }
+                            });
+                        ui.end_row();
+                    }
+
+                    if let Some(selected) = objects.get_mut(*object) {
+                        drag_vec3_row(ui, "Position", &mut selected.position);
+                        drag_vec3_row(ui, "Rotation", &mut selected.rotation);
+                        drag_vec3_row(ui, "Scale", &mut selected.scale);
+                    }
+
+                    ui.label("Shader");
+                    egui::ComboBox::from_id_source("shader")
+                        .selected_text(shader.name())
+                        .show_ui(ui, |ui| {
+                            for kind in ShaderKind::ALL {
+                                ui.selectable_value(shader, kind, kind.name());
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Deferred shading");
+                    ui.checkbox(deferred, "");
+                    ui.end_row();
+
+                    if let Some(selected) = objects.get_mut(*object) {
+                        let material = &mut selected.material;
+                        ui.label("Normal map");
+                        ui.horizontal(|ui| {
+                            //  swaps in the alternate normal map, if the scene gave one
+                            let mut space = material.normal_space;
+                            ui.radio_value(&mut space, NormalSpace::Tangent, "Tangent");
+                            ui.radio_value(&mut space, NormalSpace::Object, "Object");
+                            material.set_normal_space(space);
+                        });
+                        ui.end_row();
+
+                        ui.label("Metallic");
+                        ui.add(egui::Slider::new(&mut material.metallic, 0.0..=1.0));
+                        ui.end_row();
+
+                        ui.label("Roughness");
+                        ui.add(egui::Slider::new(&mut material.roughness, 0.0..=1.0));
+                        ui.end_row();
+                    }
+
+                    ui.label("Environment");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(environment_path)
+                            .on_hover_text("Radiance .hdr, or an 8-bit PNG whose lighting is clipped at white");
+                        if ui.button("Load").clicked() {
+                            match Environment::load(environment_path) {
+                                Ok(loaded) => {
+                                    info!("loaded environment {}", environment_path);
+                                    *environment = Some(loaded);
+                                    *environment_error = None;
+                                }
+                                Err(e) => {
+                                    warn!("loading environment {} failed: {}", environment_path, e);
+                                    *environment_error = Some(e);
+                                }
+                            }
+                        }
+                        if ui.button("Clear").clicked() {
+                            *environment = None;
+                        }
+                    });
+                    ui.end_row();
+
+                    if let Some(e) = environment_error {
+                        ui.label("");
+                        ui.colored_label(egui::Color32::RED, e.as_str());
+                        ui.end_row();
+                    }
+
+                    if let Some(environment) = environment {
+                        ui.label("Environment intensity");
+                        ui.add(egui::DragValue::new(&mut environment.intensity).speed(0.05).clamp_range(0.0..=f32::MAX));
+                        ui.end_row();
+                    }
+
+                    ui.label("Background");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut background.mode, BackgroundMode::Solid, "Solid");
+                        ui.radio_value(&mut background.mode, BackgroundMode::Gradient, "Gradient");
+                        ui.radio_value(&mut background.mode, BackgroundMode::Environment, "Environment");
+                        match background.mode {
+                            BackgroundMode::Solid => color_button(ui, &mut background.color),
+                            BackgroundMode::Gradient => {
+                                color_button(ui, &mut background.top);
+                                color_button(ui, &mut background.bottom);
+                            }
+                            BackgroundMode::Environment => {}
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("SSAO");
+                    ui.checkbox(&mut ssao.enabled, "");
+                    ui.end_row();
+
+                    if ssao.enabled {
+                        ui.label("SSAO radius");
+                        ui.add(egui::DragValue::new(&mut ssao.radius).speed(0.01).clamp_range(0.0..=f32::MAX));
+                        ui.end_row();
+
+                        ui.label("SSAO samples");
+                        ui.add(egui::Slider::new(&mut ssao.samples, 1..=64));
+                        ui.end_row();
+
+                        ui.label("SSAO strength");
+                        ui.add(egui::Slider::new(&mut ssao.strength, 0.0..=1.0));
+                        ui.end_row();
+                    }
+
+                    ui.label("Fog");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut fog.enabled, "");
+                        egui::ComboBox::from_id_source("fog_mode")
+                            .selected_text(fog.mode.name())
+                            .show_ui(ui, |ui| {
+                                for mode in FogMode::ALL {
+                                    ui.selectable_value(&mut fog.mode, mode, mode.name());
+                                }
+                            });
+                        ui.checkbox(&mut fog.per_fragment, "per fragment");
+                        color_button(ui, &mut fog.color);
+                    });
+                    ui.end_row();
+
+                    if fog.enabled {
+                        match fog.mode {
+                            FogMode::Linear => {
+                                ui.label("Fog distance");
+                                ui.horizontal(|ui| {
+                                    ui.label("start");
+                                    ui.add(egui::DragValue::new(&mut fog.start).speed(0.05).clamp_range(0.0..=f32::MAX));
+                                    ui.label("end");
+                                    ui.add(egui::DragValue::new(&mut fog.end).speed(0.05).clamp_range(0.0..=f32::MAX));
+                                });
+                                ui.end_row();
+                            }
+                            _ => {
+                                ui.label("Fog density");
+                                ui.add(egui::Slider::new(&mut fog.density, 0.0..=2.0));
+                                ui.end_row();
+                            }
+                        }
+                        if fog.mode == FogMode::Height {
+                            ui.label("Fog height");
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut fog.height).speed(0.05));
+                                ui.label("falloff");
+                                ui.add(egui::DragValue::new(&mut fog.falloff).speed(0.05).clamp_range(0.0..=f32::MAX));
+                            });
+                            ui.end_row();
+                        }
+                    }
 
                     ui.label("Display buffer");
                     ui.horizontal(|ui| {
-                        ui.radio_value(display_buffer, DisplayBuffer::Frame, "Frame");
-                        ui.radio_value(display_buffer, DisplayBuffer::Depth, "Depth");
+                        for buffer in &DisplayBuffer::ALL[..3] {
+                            ui.radio_value(display_buffer, *buffer, buffer.name());
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("G-buffer");
+                    ui.horizontal(|ui| {
+                        for buffer in &DisplayBuffer::ALL[3..] {
+                            ui.radio_value(display_buffer, *buffer, buffer.name());
+                        }
+                    });
+                    ui.end_row();
+
+                    //  whole fractions, the frame is only scaled up by whole factors
+                    ui.label("Render scale");
+                    ui.horizontal(|ui| {
+                        for (scale, name) in [(1.0, "1"), (1.0 / 2.0, "1/2"), (1.0 / 3.0, "1/3"), (1.0 / 4.0, "1/4")] {
+                            ui.radio_value(render_scale, scale, name);
+                        }
                     });
                     ui.end_row();
+
+                    ui.label("Last frame");
+                    ui.label(format!("{:.1} ms, {} rendered", stats.total.as_secs_f64() * 1000.0, render_count));
+                    ui.end_row();
                 });
             });
+
+        egui::Window::new("Lights")
+            .open(&mut self.lights_open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Add directional").clicked() {
+                        lights.push(Light::directional(Vector3::new(1.0, 1.0, 1.0)));
+                    }
+                    if ui.button("Add point").clicked() {
+                        lights.push(Light::point(Vector3::new(1.0, 1.0, 1.0)));
+                    }
+                    if ui.button("Add spot").clicked() {
+                        lights.push(Light::spot(Vector3::new(0.0, 0.0, 2.0), Vector3::new(0.0, 0.0, -1.0)));
+                    }
+                });
+
+                let mut removed = None;
+                for (i, light) in lights.iter_mut().enumerate() {
+                    ui.separator();
+                    egui::Grid::new(("light", i)).show(ui, |ui| {
+                        ui.label(light.name());
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                        ui.end_row();
+                        light_rows(ui, light);
+                    });
+                }
+                if let Some(i) = removed {
+                    lights.remove(i);
+                }
+            });
+
+        egui::Window::new("Render stats")
+            .open(&mut self.stats_open)
+            .show(ctx, |ui| {
+                egui::Grid::new("stats_counters").num_columns(2).show(ui, |ui| {
+                    let counters = [
+                        ("Triangles", stats.triangles),
+                        ("Behind near plane", stats.behind_near),
+                        ("Clipped", stats.clipped),
+                        ("Rasterized", stats.rasterized),
+                        ("Fragments", stats.fragments),
+                        ("Depth rejected", stats.depth_rejected),
+                        ("Shaded", stats.shaded),
+                        ("Discarded", stats.discarded),
+                        ("Overdraw", stats.overdraw),
+                    ];
+                    for (name, count) in counters {
+                        ui.label(name);
+                        ui.label(count.to_string());
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                //  each stage's share of the frame time
+                let total = stats.total.as_secs_f32();
+                egui::Grid::new("stats_stages").num_columns(3).show(ui, |ui| {
+                    for stage in Stage::ALL {
+                        let time = stats.stage_time(stage).as_secs_f32();
+                        ui.label(stage.name());
+                        ui.label(format!("{:.1} ms", time * 1000.0));
+                        let fraction = if total > 0.0 { time / total } else { 0.0 };
+                        ui.add(egui::ProgressBar::new(fraction).desired_width(120.0));
+                        ui.end_row();
+                    }
+                    ui.label("Total");
+                    ui.label(format!("{:.1} ms", total * 1000.0));
+                    ui.end_row();
+                });
+            });
+
+        egui::Window::new("Post-processing")
+            .open(&mut self.post_open)
+            .show(ctx, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for effect in Effect::defaults() {
+                        if ui.button(format!("Add {}", effect.name().to_lowercase())).clicked() {
+                            post_effects.push(PostEffect::new(effect));
+                        }
+                    }
+                });
+
+                //  (index, offset) of an effect to move up or down the chain
+                let mut moved = None;
+                let mut removed = None;
+                let count = post_effects.len();
+                for (i, post_effect) in post_effects.iter_mut().enumerate() {
+                    ui.separator();
+                    egui::Grid::new(("post_effect", i)).show(ui, |ui| {
+                        ui.checkbox(&mut post_effect.enabled, post_effect.effect.name());
+                        ui.horizontal(|ui| {
+                            if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                                moved = Some((i, i - 1));
+                            }
+                            if ui.add_enabled(i + 1 < count, egui::Button::new("Down")).clicked() {
+                                moved = Some((i, i + 1));
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                        ui.end_row();
+                        effect_rows(ui, &mut post_effect.effect, lut_path, lut_error, pick_focus);
+                    });
+                }
+                if let Some((i, j)) = moved {
+                    post_effects.swap(i, j);
+                }
+                if let Some(i) = removed {
+                    post_effects.remove(i);
+                }
+            });
+
+        LogConsole::show(&self.log_console, ctx);
+
+        match scene_action {
+            Some(SceneAction::LoadScene) => self.load_scene(),
+            Some(SceneAction::SaveScene) => self.save_scene(),
+            Some(SceneAction::LoadSession) => self.scene_error = self.load_session().err(),
+            Some(SceneAction::SaveSession) => self.scene_error = self.save_session().err(),
+            None => {}
+        }
+
+        if let Some(target) = browse {
+            self.file_target = target;
+            self.file_browser.browse(target.name(), target.extensions());
+        }
+        if let Some(path) = self.file_browser.show(ctx) {
+            match self.load_file(self.file_target, &path) {
+                Ok(()) => {
+                    info!("loaded {}", path);
+                    self.file_browser.open = false;
+                    self.redraw = true;
+                }
+                Err(e) => {
+                    warn!("loading {} failed: {}", path, e);
+                    self.file_browser.error = Some(e);
+                }
+            }
+        }
+    }
+
+    //  load a file chosen in the file browser into the renderer state
+    fn load_file(&mut self, target: FileTarget, path: &str) -> Result<(), String> {
+        let texture = || read_png_texture(path).map_err(|e| format!("{}: {}", path, e));
+        let mesh = || read_obj(path).map_err(|e| format!("{}: {}", path, e));
+        let objects = &mut self.renderer_state.objects;
+
+        let add = match target {
+            FileTarget::Mesh => objects.is_empty(),
+            FileTarget::Object => true,
+            _ => false,
+        };
+        if add {
+            objects.push(Object::new(mesh()?, Material::default()));
+            self.object = objects.len() - 1;
+            return Ok(());
+        }
+        let object = objects.get_mut(self.object).ok_or("no object is selected")?;
+        let material = &mut object.material;
+        match target {
+            FileTarget::Mesh => object.mesh = mesh()?.into(),
+            FileTarget::Object => {}
+            FileTarget::Diffuse => material.diffuse = texture()?.into(),
+            FileTarget::Normal => {
+                material.normal = texture()?.into();
+                material.alternate_normal = None;
+            }
+            FileTarget::MetallicRoughness => material.set_metallic_roughness(texture()?),
+            FileTarget::Occlusion => material.occlusion = Some(texture()?.into()),
+            FileTarget::Emissive => material.emissive = Some(texture()?.into()),
+        }
+        Ok(())
     }
     
 }
 
+//  name of an object in the object list
+fn object_label(object: &Object, i: usize) -> String {
+    match &object.mesh.path {
+        Some(path) => format!("{}: {}", i, path),
+        None => format!("{}: mesh", i),
+    }
+}
+
+//  Grid rows editing the parameters of a light
+fn light_rows(ui: &mut Ui, light: &mut Light) {
+    match light {
+        Light::Directional { direction, color, intensity } => {
+            drag_vec3_row(ui, "Direction", direction);
+            color_row(ui, color, intensity);
+        }
+        Light::Point { position, color, intensity, attenuation } => {
+            drag_vec3_row(ui, "Position", position);
+            color_row(ui, color, intensity);
+            attenuation_row(ui, attenuation);
+        }
+        Light::Spot { position, direction, color, intensity, attenuation, inner_angle, outer_angle } => {
+            drag_vec3_row(ui, "Position", position);
+            drag_vec3_row(ui, "Direction", direction);
+            color_row(ui, color, intensity);
+            attenuation_row(ui, attenuation);
+            ui.label("Cone angles");
+            ui.horizontal(|ui| {
+                ui.label("inner");
+                ui.add(egui::DragValue::new(inner_angle).speed(1.0).clamp_range(0.0..=90.0));
+                ui.label("outer");
+                ui.add(egui::DragValue::new(outer_angle).speed(1.0).clamp_range(0.0..=90.0));
+            });
+            ui.end_row();
+        }
+    }
+}
+
+//  Grid rows editing the parameters of a post-process effect
+fn effect_rows(ui: &mut Ui, effect: &mut Effect, lut_path: &mut String, lut_error: &mut Option<String>, pick_focus: &mut bool) {
+    match effect {
+        Effect::Blur { sigma } => {
+            ui.label("Sigma");
+            ui.add(egui::Slider::new(sigma, 0.0..=10.0));
+            ui.end_row();
+        }
+        Effect::Bloom { threshold, intensity, sigma } => {
+            ui.label("Threshold");
+            ui.add(egui::Slider::new(threshold, 0.0..=5.0))
+                .on_hover_text("HDR radiance with the PBR shader or deferred lighting, other shaders clip at 1");
+            ui.end_row();
+            ui.label("Intensity");
+            ui.add(egui::Slider::new(intensity, 0.0..=2.0));
+            ui.end_row();
+            ui.label("Sigma");
+            ui.add(egui::Slider::new(sigma, 0.0..=20.0));
+            ui.end_row();
+        }
+        Effect::Fxaa => {}
+        Effect::Vignette { strength, radius } => {
+            ui.label("Strength");
+            ui.add(egui::Slider::new(strength, 0.0..=1.0));
+            ui.end_row();
+            ui.label("Radius");
+            ui.add(egui::Slider::new(radius, 0.0..=1.4));
+            ui.end_row();
+        }
+        Effect::ColorGrade { lut, strength } => {
+            ui.label("LUT");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(lut_path);
+                if ui.button("Load").clicked() {
+                    match read_png_texture(lut_path).map_err(|e| e.to_string()).and_then(|t| Lut::from_texture(&t)) {
+                        Ok(loaded) => {
+                            info!("loaded lookup table {}", lut_path);
+                            *lut = loaded.into();
+                            *lut_error = None;
+                        }
+                        Err(e) => {
+                            warn!("loading lookup table {} failed: {}", lut_path, e);
+                            *lut_error = Some(e);
+                        }
+                    }
+                }
+                if ui.button("Reset").clicked() {
+                    *lut = Lut::neutral(16).into();
+                }
+            });
+            ui.end_row();
+            if let Some(e) = lut_error {
+                ui.label("");
+                ui.colored_label(egui::Color32::RED, e.as_str());
+                ui.end_row();
+            }
+            ui.label("Strength");
+            ui.add(egui::Slider::new(strength, 0.0..=1.0));
+            ui.end_row();
+        }
+        Effect::DepthOfField { focal_distance, aperture, bokeh } => {
+            ui.label("Focal distance");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(focal_distance).speed(0.01).clamp_range(0.0..=f32::MAX));
+                let label = if *pick_focus { "Click the frame..." } else { "Pick" };
+                if ui.button(label).clicked() {
+                    *pick_focus = !*pick_focus;
+                }
+            });
+            ui.end_row();
+            ui.label("Aperture");
+            ui.add(egui::Slider::new(aperture, 0.0..=0.2));
+            ui.end_row();
+            ui.label("Bokeh");
+            ui.horizontal(|ui| {
+                let mut polygon = matches!(bokeh, Bokeh::Polygon { .. });
+                ui.radio_value(&mut polygon, false, "Circle");
+                ui.radio_value(&mut polygon, true, "Polygon");
+                match (polygon, &mut *bokeh) {
+                    (false, _) => *bokeh = Bokeh::Circle,
+                    (true, Bokeh::Polygon { blades }) => {
+                        ui.add(egui::Slider::new(blades, 3..=9).text("blades"));
+                    }
+                    (true, Bokeh::Circle) => *bokeh = Bokeh::Polygon { blades: 6 },
+                }
+            });
+            ui.end_row();
+        }
+        Effect::Outline { threshold, normal_weight, color } => {
+            ui.label("Threshold");
+            ui.add(egui::Slider::new(threshold, 0.0..=2.0));
+            ui.end_row();
+            ui.label("Normal weight");
+            ui.add(egui::Slider::new(normal_weight, 0.0..=1.0));
+            ui.end_row();
+            ui.label("Color");
+            color_button(ui, color);
+            ui.end_row();
+        }
+    }
+}
+
+fn color_button(ui: &mut Ui, color: &mut Vector3) {
+    let mut rgb = color.to_array();
+    ui.color_edit_button_rgb(&mut rgb);
+    *color = Vector3::from(rgb);
+}
+
+//  A grid row with a color picker and intensity
+fn color_row(ui: &mut Ui, color: &mut Vector3, intensity: &mut f32) {
+    ui.label("Color");
+    ui.horizontal(|ui| {
+        color_button(ui, color);
+        ui.label("intensity");
+        ui.add(egui::DragValue::new(intensity).speed(0.05).clamp_range(0.0..=f32::MAX));
+    });
+    ui.end_row();
+}
+
+fn attenuation_row(ui: &mut Ui, attenuation: &mut Attenuation) {
+    ui.label("Attenuation");
+    ui.horizontal(|ui| {
+        ui.label("const");
+        ui.add(egui::DragValue::new(&mut attenuation.constant).speed(0.01).clamp_range(0.0..=f32::MAX));
+        ui.label("lin");
+        ui.add(egui::DragValue::new(&mut attenuation.linear).speed(0.01).clamp_range(0.0..=f32::MAX));
+        ui.label("quad");
+        ui.add(egui::DragValue::new(&mut attenuation.quadratic).speed(0.01).clamp_range(0.0..=f32::MAX));
+    });
+    ui.end_row();
+}
+
 //  A grid row with a label and 3 draggable numbers bound to a Vector3 
 fn drag_vec3_row(ui: &mut Ui, label: &str, v: &mut Vector3) {
     ui.label(label);
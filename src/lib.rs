@@ -2,7 +2,23 @@
 #![allow(clippy::many_single_char_names)]
 
 pub mod renderer;
+pub mod shader;
+pub mod environment;
+pub mod ssao;
+pub mod deferred;
+pub mod postprocess;
+pub mod light;
+pub mod fog;
+pub mod stats;
+pub mod scene;
+pub mod render_thread;
+pub mod mesh;
 pub mod objloader;
 pub mod geometry;
 pub mod util;
-pub mod gui;
\ No newline at end of file
+#[cfg(feature = "gui")]
+pub mod gui;
+#[cfg(feature = "gui")]
+pub mod file_browser;
+#[cfg(feature = "gui")]
+pub mod log_console;
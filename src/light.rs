@@ -0,0 +1,115 @@
+use crate::geometry::Vector3;
+
+/// Distance attenuation of point and spot lights, `1 / (constant + linear*d + quadratic*d^2)`
+#[derive(Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub fn new(constant: f32, linear: f32, quadratic: f32) -> Self {
+        Self { constant, linear, quadratic }
+    }
+
+    pub fn factor(&self, distance: f32) -> f32 {
+        let d = self.constant + self.linear * distance + self.quadratic * distance * distance;
+        if d > 0.0 { 1.0 / d } else { 1.0 }
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self::new(1.0, 0.0, 0.25)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Light arriving from `direction` (pointing towards the light) everywhere
+    Directional {
+        direction: Vector3,
+        color: Vector3,
+        intensity: f32,
+    },
+    Point {
+        position: Vector3,
+        color: Vector3,
+        intensity: f32,
+        attenuation: Attenuation,
+    },
+    /// Point light restricted to a cone around `direction` (pointing away from
+    /// the light), fading between the inner and outer cone angles in degrees
+    Spot {
+        position: Vector3,
+        direction: Vector3,
+        color: Vector3,
+        intensity: f32,
+        attenuation: Attenuation,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+impl Light {
+    pub fn directional(direction: Vector3) -> Self {
+        Light::Directional { direction, color: Vector3::ONE, intensity: 1.0 }
+    }
+
+    pub fn point(position: Vector3) -> Self {
+        Light::Point { position, color: Vector3::ONE, intensity: 1.0, attenuation: Attenuation::default() }
+    }
+
+    pub fn spot(position: Vector3, direction: Vector3) -> Self {
+        Light::Spot {
+            position,
+            direction,
+            color: Vector3::ONE,
+            intensity: 1.0,
+            attenuation: Attenuation::default(),
+            inner_angle: 15.0,
+            outer_angle: 25.0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Light::Directional { .. } => "Directional",
+            Light::Point { .. } => "Point",
+            Light::Spot { .. } => "Spot",
+        }
+    }
+
+    /// Unit direction from a world position towards the light and the
+    /// colored intensity arriving at that position
+    pub fn incident(&self, position: Vector3) -> (Vector3, Vector3) {
+        match *self {
+            Light::Directional { direction, color, intensity } => {
+                (direction.normalize_or_zero(), color * intensity)
+            }
+            Light::Point { position: light_position, color, intensity, attenuation } => {
+                let to_light = light_position - position;
+                let distance = to_light.length();
+                (to_light.normalize_or_zero(), color * intensity * attenuation.factor(distance))
+            }
+            Light::Spot { position: light_position, direction, color, intensity, attenuation, inner_angle, outer_angle } => {
+                let to_light = light_position - position;
+                let distance = to_light.length();
+                let l = to_light.normalize_or_zero();
+
+                let cos_angle = Vector3::dot(-l, direction.normalize_or_zero());
+                let (cos_inner, cos_outer) = (inner_angle.to_radians().cos(), outer_angle.to_radians().cos());
+                let cone = if cos_inner > cos_outer {
+                    ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0)
+                } else if cos_angle >= cos_outer {
+                    1.0
+                } else {
+                    0.0
+                };
+
+                (l, color * intensity * attenuation.factor(distance) * cone)
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use egui::{Color32, CtxRef};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+//  records kept, older ones are dropped
+const MAX_RECORDS: usize = 1000;
+
+const LEVELS: [LevelFilter; 5] = [LevelFilter::Error, LevelFilter::Warn, LevelFilter::Info, LevelFilter::Debug, LevelFilter::Trace];
+
+struct Entry {
+    level: Level,
+    target: String,
+    message: String,
+}
+
+/// Recent log records for the console window.  Records up to `level` are
+/// kept whatever `RUST_LOG` lets through to stderr.
+pub struct LogConsole {
+    pub open: bool,
+    level: LevelFilter,
+    //  level of the stderr logger, both are let through the log facade
+    env_level: LevelFilter,
+    entries: VecDeque<Entry>,
+}
+
+impl LogConsole {
+    /// Draw the console window while open.  The entries are copied out and
+    /// drawn with the lock released, so anything logging meanwhile doesn't
+    /// wait on it.
+    pub fn show(console: &Mutex<LogConsole>, ctx: &CtxRef) {
+        let (mut open, mut level, lines) = match console.lock() {
+            Ok(console) if console.open => {
+                let lines: Vec<(Level, String)> = console.entries.iter()
+                    .map(|entry| (entry.level, format!("{:<5} {}: {}", entry.level, entry.target, entry.message)))
+                    .collect();
+                (true, console.level, lines)
+            }
+            _ => return,
+        };
+        let mut clear = false;
+
+        egui::Window::new("Log")
+            .open(&mut open)
+            .default_size((500.0, 300.0))
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Level")
+                        .selected_text(level.to_string())
+                        .show_ui(ui, |ui| {
+                            for filter in LEVELS {
+                                ui.selectable_value(&mut level, filter, filter.to_string());
+                            }
+                        });
+                    clear = ui.button("Clear").clicked();
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical().stick_to_bottom().show(ui, |ui| {
+                    for (level, text) in lines {
+                        ui.colored_label(level_color(level), text);
+                    }
+                });
+            });
+
+        if let Ok(mut console) = console.lock() {
+            console.open = open;
+            if level != console.level {
+                console.set_level(level);
+            }
+            if clear {
+                console.entries.clear();
+            }
+        }
+    }
+
+    fn set_level(&mut self, level: LevelFilter) {
+        self.level = level;
+        self.entries.retain(|entry| entry.level <= level);
+        log::set_max_level(level.max(self.env_level));
+    }
+
+    fn push(&mut self, record: &Record) {
+        if self.entries.len() == MAX_RECORDS {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        });
+    }
+}
+
+fn level_color(level: Level) -> Color32 {
+    match level {
+        Level::Error => Color32::RED,
+        Level::Warn => Color32::YELLOW,
+        Level::Info => Color32::LIGHT_GRAY,
+        Level::Debug | Level::Trace => Color32::GRAY,
+    }
+}
+
+//  logs to stderr as configured by `RUST_LOG` and to the console
+struct ConsoleLogger {
+    env_logger: env_logger::Logger,
+    console: Arc<Mutex<LogConsole>>,
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.env_logger.enabled(metadata) || self.console_enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.env_logger.matches(record) {
+            self.env_logger.log(record);
+        }
+        if self.console_enabled(record.metadata()) {
+            if let Ok(mut console) = self.console.lock() {
+                console.push(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.env_logger.flush();
+    }
+}
+
+impl ConsoleLogger {
+    fn console_enabled(&self, metadata: &Metadata) -> bool {
+        matches!(self.console.lock(), Ok(console) if metadata.level() <= console.level)
+    }
+}
+
+/// Install the logger, in place of `env_logger::init`, keeping records up
+/// to `level` for the console
+pub fn init(level: LevelFilter) -> Arc<Mutex<LogConsole>> {
+    let env_logger = env_logger::Builder::from_default_env().build();
+    let env_level = env_logger.filter();
+    let console = Arc::new(Mutex::new(LogConsole {
+        open: false,
+        level,
+        env_level,
+        entries: VecDeque::new(),
+    }));
+    let logger = ConsoleLogger { env_logger, console: console.clone() };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level.max(env_level));
+    }
+    console
+}
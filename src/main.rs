@@ -1,35 +1,98 @@
 #![deny(clippy::all)]
 #![forbid(unsafe_code)]
 
-use log::error;
+use std::{env, mem};
+use std::time::Instant;
+
+use log::{error, LevelFilter};
 use pixels::{Error, Pixels, SurfaceTexture};
-use winit::dpi::LogicalSize;
+use winit::dpi::{LogicalSize, PhysicalSize};
 use winit::event::{Event, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-use tinyrenderer::renderer::{Renderer,  RendererState};
-use tinyrenderer::util::{save_png};
+use tinyrenderer::renderer::{Camera, Renderer};
+use tinyrenderer::postprocess::Effect;
+use tinyrenderer::render_thread::RenderThread;
 use tinyrenderer::gui::Framework;
+use tinyrenderer::log_console;
 
 const WIDTH: i32 = 1000;
 const HEIGHT: i32 = 1000;
+//  smallest window, in logical pixels
+const MIN_WIDTH: i32 = 320;
+const MIN_HEIGHT: i32 = 240;
+
+//  degrees turned per pixel dragged
+const TURN_SPEED: f32 = 0.3;
+//  fraction of the eye to center distance panned per pixel dragged
+const PAN_SPEED: f32 = 0.002;
+//  distance scale per line scrolled
+const DOLLY_STEP: f32 = 0.9;
+//  world units per second flown, shift flies faster
+const FLY_SPEED: f32 = 1.5;
+const FLY_FAST: f32 = 4.0;
+
+/// Frame size for a window of `size` at `scale` frame pixels per window pixel
+fn frame_size(size: PhysicalSize<u32>, scale: f32) -> (i32, i32) {
+    let scaled = |n: u32| ((n as f32 * scale) as i32).max(1);
+    (scaled(size.width), scaled(size.height))
+}
 
-fn draw(r: &mut Renderer, s: &RendererState) {
-    r.draw_mesh_shader(s);
+/// Move the camera from the mouse and keys, returns true when it moved.
+/// Orbit mode: left drag orbits the center, right or middle drag (or shift
+/// left drag) pans and scrolling dollies.  Fly mode: dragging turns the view,
+/// WASD moves, Q and E move down and up and scrolling moves forward.
+/// `drag` is set while a button pressed over the frame is held, `pointer`
+/// and `keys` while egui doesn't want the mouse and keyboard.
+fn control_camera(input: &WinitInputHelper, camera: &mut Camera, fly: bool, dt: f32, drag: bool, pointer: bool, keys: bool) -> bool {
+    let before = camera.clone();
+    let (dx, dy) = if drag { input.mouse_diff() } else { (0.0, 0.0) };
+    let scroll = if pointer { input.scroll_diff() } else { 0.0 };
+    let pan = input.mouse_held(1) || input.mouse_held(2) || input.held_shift();
+
+    if fly {
+        if drag {
+            camera.look(-dx * TURN_SPEED, -dy * TURN_SPEED);
+        }
+        let speed = FLY_SPEED * if input.held_shift() { FLY_FAST } else { 1.0 };
+        let axis = |positive, negative| {
+            let held = |key| if keys && input.key_held(key) { 1.0 } else { 0.0 };
+            held(positive) - held(negative)
+        };
+        let forward = axis(VirtualKeyCode::W, VirtualKeyCode::S) * speed * dt + scroll * speed * 0.25;
+        let right = axis(VirtualKeyCode::D, VirtualKeyCode::A) * speed * dt;
+        let up = axis(VirtualKeyCode::E, VirtualKeyCode::Q) * speed * dt;
+        if forward != 0.0 || right != 0.0 || up != 0.0 {
+            camera.fly(forward, right, up);
+        }
+    } else {
+        if drag && pan {
+            camera.pan(-dx * PAN_SPEED, dy * PAN_SPEED);
+        } else if drag {
+            camera.orbit(-dx * TURN_SPEED, dy * TURN_SPEED);
+        }
+        if scroll != 0.0 {
+            camera.dolly(DOLLY_STEP.powf(scroll));
+        }
+    }
+    *camera != before
 }
 
 fn main() -> Result<(), Error> {
-    env_logger::init();
+    let log_console = log_console::init(LevelFilter::Info);
+    //  tinyrenderer [SCENE], without a scene the last session is restored
+    let scene_path = env::args().nth(1);
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
     let window = {
         let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
+        let min_size = LogicalSize::new(MIN_WIDTH as f64, MIN_HEIGHT as f64);
         WindowBuilder::new()
             .with_title("tinyrenderer")
             .with_inner_size(size)
-            .with_min_inner_size(size)
+            .with_min_inner_size(min_size)
             .build(&event_loop)
             .unwrap()
     };
@@ -38,33 +101,41 @@ fn main() -> Result<(), Error> {
         let window_size = window.inner_size();
         let scale_factor = window.scale_factor() as f32;
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        let pixels = Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture)?;
-        let framework = Framework::new(window_size.width, window_size.height, scale_factor, &pixels);
+        let (width, height) = frame_size(window_size, 1.0);
+        let pixels = Pixels::new(width as u32, height as u32, surface_texture)?;
+        let framework = Framework::new(window_size.width, window_size.height, scale_factor, &pixels, scene_path.as_deref(), log_console);
 
         (pixels, framework)
     };
 
-    let mut renderer = Renderer::new(WIDTH, HEIGHT);
-    // let mut renderer_state = RendererState{
-    //     model: Vector3::ZERO,
-    //     eye: Vector3::new(1.0, 1.0, 3.0),
-    //     center: Vector3::ZERO,
-    //     up: Vector3::new(0.0, 1.0, 0.0),
-    //     light_dir: Vector3::new(1.0, 1.0, 1.0),
-    //     rotation: Vector3::ZERO,
-    // };
-    // renderer.clear();
-    draw(&mut renderer, &framework.gui.renderer_state);
-
-    save_png("shaded.png", renderer.width as u32, renderer.height as u32, renderer.buf.as_slice());
-    save_png("zbuf.png", renderer.width as u32, renderer.height as u32, renderer.zbuf_buf().as_slice());
-
+    //  frames are drawn on the render thread, `renderer` is the one
+    //  displayed, blank until the first one is finished
+    let (width, height) = frame_size(window.inner_size(), framework.gui.render_scale);
+    let mut renderer = Renderer::new(width, height);
+    let mut render_thread = RenderThread::new();
+    //  what the last requested frame draws, to only draw again on changes,
+    //  cheap to copy and compare as the meshes and textures are shared
+    let mut requested_size = (width, height);
+    let mut requested_state = framework.gui.renderer_state.clone();
+    if let Err(e) = render_thread.request(requested_state.clone(), width, height) {
+        error!("{}", e);
+    }
+    //  whether a mouse button went down over the frame and is still held
+    let mut dragging = false;
+    let mut last_update = Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
         // Handle input events
         if input.update(&event) {
+            let now = Instant::now();
+            let dt = (now - last_update).as_secs_f32();
+            last_update = now;
+
             // Close events
             if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+                if let Err(e) = framework.gui.save_session() {
+                    error!("saving the session failed: {}", e);
+                }
                 *control_flow = ControlFlow::Exit;
                 return;
             }
@@ -72,6 +143,47 @@ fn main() -> Result<(), Error> {
             // Resize the window
             if let Some(size) = input.window_resized() {
                 pixels.resize_surface(size.width, size.height);
+                framework.resize(size.width, size.height);
+            }
+            if let Some(scale_factor) = input.scale_factor_changed() {
+                framework.scale_factor(scale_factor);
+            }
+
+            // Focus depth of field at the clicked pixel, the click doesn't
+            // also start a drag
+            let pressed = (0..3).any(|button| input.mouse_pressed(button));
+            if framework.gui.pick_focus && input.mouse_pressed(0) && !framework.wants_pointer_input() {
+                let pixel = input.mouse().and_then(|pos| pixels.window_pos_to_pixel(pos).ok());
+                if let Some((x, y)) = pixel {
+                    let state = &mut framework.gui.renderer_state;
+                    let depth = renderer.linear_depth(state.camera())[y * renderer.width as usize + x];
+                    if depth.is_finite() {
+                        for post_effect in &mut state.post_effects {
+                            if let Effect::DepthOfField { focal_distance, .. } = &mut post_effect.effect {
+                                *focal_distance = depth;
+                            }
+                        }
+                        framework.gui.pick_focus = false;
+                        framework.gui.redraw = true;
+                    }
+                }
+            } else if pressed && !framework.wants_pointer_input() && !framework.gui.pick_focus {
+                dragging = true;
+            }
+
+            // Move the camera with input egui doesn't want
+            if !(0..3).any(|button| input.mouse_held(button)) {
+                dragging = false;
+            }
+            let keys = !framework.wants_keyboard_input();
+            if keys && input.key_pressed(VirtualKeyCode::F) {
+                framework.gui.fly_mode = !framework.gui.fly_mode;
+            }
+            let pointer = !framework.wants_pointer_input();
+            let fly = framework.gui.fly_mode;
+            let camera = framework.gui.renderer_state.camera_mut();
+            if control_camera(&input, camera, fly, dt, dragging, pointer, keys) {
+                framework.gui.redraw = true;
             }
 
             // Update internal state and request a redraw
@@ -83,13 +195,35 @@ fn main() -> Result<(), Error> {
             Event::WindowEvent { event, .. } => {
                 // Update egui inputs
                 if framework.handle_event(&event) {
-                    renderer.clear();
-                    draw(&mut renderer, &framework.gui.renderer_state);
+                    framework.gui.redraw = true;
                 }
             }
             Event::RedrawRequested(_) => {
-                renderer.draw(pixels.get_frame(), framework.gui.renderer_state.display_buffer);
                 framework.prepare(&window);
+                //  follow the window size and render scale
+                let size = frame_size(window.inner_size(), framework.gui.render_scale);
+                if framework.gui.redraw || size != requested_size {
+                    framework.gui.redraw = false;
+                    let state = &framework.gui.renderer_state;
+                    if size != requested_size || *state != requested_state {
+                        requested_size = size;
+                        requested_state = state.clone();
+                        if let Err(e) = render_thread.request(state.clone(), size.0, size.1) {
+                            error!("{}", e);
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                }
+                //  show the latest finished frame, the previous one stays up meanwhile
+                if let Some(frame) = render_thread.try_frame() {
+                    if frame.width != renderer.width || frame.height != renderer.height {
+                        pixels.resize_buffer(frame.width as u32, frame.height as u32);
+                    }
+                    framework.gui.stats = frame.stats;
+                    framework.gui.render_count += 1;
+                    render_thread.recycle(mem::replace(&mut renderer, frame));
+                }
+                renderer.draw(pixels.get_frame(), framework.gui.renderer_state.display_buffer);
                 let render_result = pixels.render_with(|encoder, render_target, context| {
                     // Render the world texture
                     context.scaling_renderer.render(encoder, render_target);
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use crate::geometry::Vector3;
+use crate::renderer::Mesh;
+
+/// Crease angle used when generating normals for faces loaded without them
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
+/// How face normals are weighted when averaged into smooth vertex normals
+#[derive(Clone, Copy, PartialEq)]
+pub enum NormalWeighting {
+    Area,
+    Angle,
+}
+
+//  any unit vector perpendicular to n
+pub(crate) fn perpendicular(n: Vector3) -> Vector3 {
+    let axis = if n.x.abs() < 0.9 { Vector3::X } else { Vector3::Y };
+    Vector3::cross(n, axis).normalize()
+}
+
+//  remove the component of v along unit vector n
+fn project_onto_plane(v: Vector3, n: Vector3) -> Vector3 {
+    v - n * Vector3::dot(n, v)
+}
+
+impl Mesh {
+    //  cross product of each triangle's edges, its length is twice the area
+    fn face_cross_products(&self) -> Vec<Vector3> {
+        self.indexes.chunks_exact(3).map(|tri| {
+            let p = [self.vs[tri[0].vertex], self.vs[tri[1].vertex], self.vs[tri[2].vertex]];
+            Vector3::cross(p[1] - p[0], p[2] - p[0])
+        }).collect()
+    }
+
+    //  angle of the triangle at one of its corners
+    fn corner_angle(&self, corner: usize) -> f32 {
+        let face = corner - corner % 3;
+        let p = |i: usize| self.vs[self.indexes[face + (corner + i) % 3].vertex];
+        let angle = Vector3::angle_between(p(1) - p(0), p(2) - p(0));
+        if angle.is_finite() { angle } else { 0.0 }
+    }
+
+    /// Unit normal of each triangle, following counter-clockwise winding
+    pub fn face_normals(&self) -> Vec<Vector3> {
+        self.face_cross_products().iter().map(|n| n.normalize_or_zero()).collect()
+    }
+
+    /// Replace the mesh normals with one normal per face
+    pub fn generate_flat_normals(&mut self) {
+        let face_normals = self.face_normals();
+
+        self.ns.truncate(1);
+        for (face, n) in face_normals.into_iter().enumerate() {
+            self.ns.push(n);
+            for index in &mut self.indexes[face * 3..face * 3 + 3] {
+                index.normal = self.ns.len() - 1;
+            }
+        }
+        self.nis = self.indexes.iter().map(|index| index.normal as i32).collect();
+    }
+
+    /// Replace the mesh normals with smooth vertex normals, averaging the
+    /// normals of faces sharing a vertex.  Faces whose normals differ by more
+    /// than `crease_angle` (radians) are not averaged, splitting the vertex so
+    /// hard edges stay sharp.
+    pub fn generate_smooth_normals(&mut self, weighting: NormalWeighting, crease_angle: f32) {
+        self.ns.truncate(1);
+        self.smooth_normals(weighting, crease_angle, |_| true);
+    }
+
+    /// Generate smooth normals, as `generate_smooth_normals` does, for the
+    /// faces with a corner missing its normal, keeping the normals of the rest
+    pub fn generate_missing_normals(&mut self, weighting: NormalWeighting, crease_angle: f32) {
+        let missing: Vec<bool> = self.indexes.chunks_exact(3)
+            .map(|tri| tri.iter().any(|index| index.normal == 0))
+            .collect();
+        if missing.contains(&true) {
+            self.smooth_normals(weighting, crease_angle, |corner| missing[corner / 3]);
+        }
+    }
+
+    //  smooth normals of the corners `fill` selects, added to `ns`
+    fn smooth_normals(&mut self, weighting: NormalWeighting, crease_angle: f32, fill: impl Fn(usize) -> bool) {
+        let crosses = self.face_cross_products();
+        let face_normals: Vec<Vector3> = crosses.iter().map(|n| n.normalize_or_zero()).collect();
+        let weights: Vec<f32> = (0..self.indexes.len()).map(|corner| match weighting {
+            NormalWeighting::Area => crosses[corner / 3].length(),
+            NormalWeighting::Angle => self.corner_angle(corner),
+        }).collect();
+
+        let mut vertex_corners: Vec<Vec<usize>> = vec![vec![]; self.vs.len()];
+        for (corner, index) in self.indexes.iter().enumerate() {
+            vertex_corners[index.vertex].push(corner);
+        }
+
+        let cos_crease = crease_angle.cos();
+        let mut normal_indexes: HashMap<(usize, [u32; 3]), usize> = HashMap::new();
+
+        for corner in (0..self.indexes.len()).filter(|corner| fill(*corner)) {
+            let vertex = self.indexes[corner].vertex;
+            let face_normal = face_normals[corner / 3];
+
+            let n = vertex_corners[vertex].iter()
+                .filter(|other| Vector3::dot(face_normal, face_normals[**other / 3]) >= cos_crease)
+                .map(|other| face_normals[*other / 3] * weights[*other])
+                .fold(Vector3::ZERO, |l, r| l + r)
+                .normalize_or_zero();
+
+            //  corners of a vertex averaging the same faces share a normal
+            let ns = &mut self.ns;
+            let normal = *normal_indexes.entry((vertex, n.to_array().map(f32::to_bits))).or_insert_with(|| {
+                ns.push(n);
+                ns.len() - 1
+            });
+            self.indexes[corner].normal = normal;
+        }
+        self.nis = self.indexes.iter().map(|index| index.normal as i32).collect();
+    }
+
+    /// Generate per-vertex tangents in the manner of MikkTSpace: per-face
+    /// tangent and bitangent are projected into each corner's tangent plane and
+    /// accumulated weighted by the corner angle over corners sharing position,
+    /// uv, normal and uv orientation.  Tangents are stored in `ts` with the
+    /// handedness of the bitangent in w, so `bitangent = w * cross(n, t)`.
+    pub fn generate_tangents(&mut self) {
+        let mut groups: HashMap<(usize, usize, usize, bool), usize> = HashMap::new();
+        let mut tangents: Vec<Vector3> = vec![];
+        let mut bitangents: Vec<Vector3> = vec![];
+        let mut normals: Vec<Vector3> = vec![];
+        let mut corner_groups = vec![0; self.indexes.len()];
+
+        for (face, tri) in self.indexes.chunks_exact(3).enumerate() {
+            let p = [self.vs[tri[0].vertex], self.vs[tri[1].vertex], self.vs[tri[2].vertex]];
+            let uv = [self.tex[tri[0].tex], self.tex[tri[1].tex], self.tex[tri[2].tex]];
+
+            let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+            let (duv1, duv2) = (uv[1] - uv[0], uv[2] - uv[0]);
+            let r = duv1.x * duv2.y - duv2.x * duv1.y;
+
+            //  degenerate uv mapping, tangents are chosen arbitrarily at finalize
+            let (t, b) = if r.abs() > f32::EPSILON {
+                ((e1 * duv2.y - e2 * duv1.y) / r, (e2 * duv1.x - e1 * duv2.x) / r)
+            } else {
+                (Vector3::ZERO, Vector3::ZERO)
+            };
+
+            for i in 0..3 {
+                let index = &tri[i];
+                let n = self.ns[index.normal].normalize_or_zero();
+                let angle = Vector3::angle_between(p[(i + 1) % 3] - p[i], p[(i + 2) % 3] - p[i]);
+                let angle = if angle.is_finite() { angle } else { 0.0 };
+
+                let key = (index.vertex, index.tex, index.normal, r >= 0.0);
+                let group = *groups.entry(key).or_insert_with(|| {
+                    tangents.push(Vector3::ZERO);
+                    bitangents.push(Vector3::ZERO);
+                    normals.push(n);
+                    tangents.len() - 1
+                });
+
+                tangents[group] += project_onto_plane(t, n).normalize_or_zero() * angle;
+                bitangents[group] += project_onto_plane(b, n).normalize_or_zero() * angle;
+                corner_groups[face * 3 + i] = group;
+            }
+        }
+
+        //  ts[0] is reserved like the other attribute arrays
+        self.ts.truncate(1);
+        for ((t, b), n) in tangents.iter().zip(bitangents.iter()).zip(normals.iter()) {
+            let mut tangent = project_onto_plane(*t, *n).normalize_or_zero();
+            if tangent == Vector3::ZERO {
+                tangent = perpendicular(*n);
+            }
+            let w = if Vector3::dot(Vector3::cross(*n, tangent), *b) < 0.0 { -1.0 } else { 1.0 };
+            self.ts.push(tangent.extend(w));
+        }
+
+        for (index, group) in self.indexes.iter_mut().zip(corner_groups) {
+            index.tangent = group + 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Vector2, Vector4};
+    use crate::renderer::Index;
+
+    //  a unit quad facing +z, mapped with u along x or mirrored
+    fn quad(mirrored: bool) -> Mesh {
+        let mut mesh = Mesh::new();
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        mesh.vs.extend(corners.iter().map(|(x, y)| Vector3::new(*x, *y, 0.0)));
+        mesh.tex.extend(corners.iter().map(|(x, y)| Vector2::new(if mirrored { 1.0 - x } else { *x }, *y)));
+        mesh.ns.push(Vector3::Z);
+        mesh.indexes.extend([1, 2, 3, 1, 3, 4].map(|v| Index::new(v, v, 1)));
+        mesh
+    }
+
+    fn tangents(mesh: &Mesh) -> Vec<Vector4> {
+        mesh.indexes.iter().map(|index| mesh.ts[index.tangent]).collect()
+    }
+
+    #[test]
+    fn tangents_follow_u_with_right_handed_bitangents() {
+        let mut mesh = quad(false);
+        mesh.generate_tangents();
+        for t in tangents(&mesh) {
+            assert!(t.abs_diff_eq(Vector4::new(1.0, 0.0, 0.0, 1.0), 1e-6), "{}", t);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_handedness() {
+        let mut mesh = quad(true);
+        mesh.generate_tangents();
+        for t in tangents(&mesh) {
+            assert!(t.abs_diff_eq(Vector4::new(-1.0, 0.0, 0.0, -1.0), 1e-6), "{}", t);
+        }
+    }
+
+    #[test]
+    fn missing_normals_are_generated_and_given_ones_kept() {
+        let mut mesh = quad(false);
+        mesh.ns[1] = Vector3::new(0.0, 1.0, 1.0).normalize();
+        mesh.indexes[3..].iter_mut().for_each(|index| index.normal = 0);
+        mesh.generate_missing_normals(NormalWeighting::Angle, DEFAULT_CREASE_ANGLE);
+
+        assert!(mesh.indexes[..3].iter().all(|index| index.normal == 1));
+        for index in &mesh.indexes[3..] {
+            assert!(mesh.ns[index.normal].abs_diff_eq(Vector3::Z, 1e-6));
+        }
+    }
+}
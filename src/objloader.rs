@@ -1,25 +1,32 @@
 use std::fs;
 use regex::Regex;
 
-use crate::{geometry::{Vector2, Vector3}, renderer::{Mesh, Index}};
+use crate::{geometry::{Vector2, Vector3, Vector4}, renderer::{Mesh, Index}, mesh::{NormalWeighting, DEFAULT_CREASE_ANGLE}};
 
 pub fn load_obj(path: &str) -> Mesh {
+    read_obj(path).unwrap()
+}
+
+pub fn read_obj(path: &str) -> Result<Mesh, String> {
     let mut mesh = Mesh::new();
+    mesh.path = Some(path.to_string());
 
-    // hacking an obj reader with no error handling
-    let content = fs::read_to_string(path).expect("Error reading file");
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
     let lines = content.split("\n");
 
     let re = Regex::new(r" +").expect("Invalid regex");
 
-    for line in lines {
-        let mut tokens = re.split(line);
+    for (number, line) in lines.enumerate() {
+        let invalid = || format!("line {}: invalid '{}'", number + 1, line.trim());
+        let mut tokens = re.split(line.trim());
         let field = tokens.next();
         match field {
             Some(t @ ("v" | "vt" | "vn")) => {
-                let x = tokens.next().unwrap().parse::<f32>().unwrap();
-                let y = tokens.next().unwrap().parse::<f32>().unwrap();
-                let z = tokens.next().unwrap().parse::<f32>().unwrap();
+                let mut coord = || tokens.next().and_then(|c| c.parse::<f32>().ok()).ok_or_else(invalid);
+                let x = coord()?;
+                let y = coord()?;
+                //  texture coords may omit w
+                let z = coord().unwrap_or(0.0);
                 
                 match t {
                     "v" => mesh.vs.push(Vector3::new(x, y, z)),
@@ -30,12 +37,13 @@ pub fn load_obj(path: &str) -> Mesh {
             },
             Some("f") => {
                 for _ in 0..3 {
-                    let mut triple_iter = tokens.next().unwrap().split("/");
-                    let vi = triple_iter.next().unwrap().parse::<i32>().unwrap();
+                    let mut triple_iter = tokens.next().ok_or_else(invalid)?.split('/');
+                    let vi = triple_iter.next().and_then(|v| v.parse::<i32>().ok()).ok_or_else(invalid)?;
                     mesh.vis.push(vi);
-                    let ti = triple_iter.next().unwrap().parse::<i32>().unwrap();
+                    //  missing texture or normal indices (v, v/t, v//n) refer to the zero entry
+                    let ti = triple_iter.next().and_then(|t| t.parse::<i32>().ok()).unwrap_or(0);
                     mesh.tis.push(ti);
-                    let ni = triple_iter.next().unwrap().parse::<i32>().unwrap();
+                    let ni = triple_iter.next().and_then(|n| n.parse::<i32>().ok()).unwrap_or(0);
                     mesh.nis.push(ni);
                     // XXX convert negative indices to positive
                     mesh.indexes.push(Index::new(vi as usize, ti as usize, ni as usize));
@@ -45,8 +53,16 @@ pub fn load_obj(path: &str) -> Mesh {
             Some(_) | None => { },
         }
     }
-    
-    mesh
+
+    let in_range = |i: &Index| i.vertex < mesh.vs.len() && i.tex < mesh.tex.len() && i.normal < mesh.ns.len();
+    if !mesh.indexes.iter().all(in_range) {
+        return Err("face index out of range".to_string());
+    }
+
+    //  faces given without normals, in all or part of the file
+    mesh.generate_missing_normals(NormalWeighting::Angle, DEFAULT_CREASE_ANGLE);
+    mesh.generate_tangents();
+    Ok(mesh)
 }
 
 impl Mesh {
@@ -58,7 +74,9 @@ impl Mesh {
             tis: vec![],
             ns: vec![Vector3::ZERO],
             nis: vec![],
+            ts: vec![Vector4::ZERO],
             indexes: vec![],
+            path: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,363 @@
+use std::f32::consts::PI;
+
+use crate::geometry::{Vector2, Vector3};
+use crate::renderer::{Camera, Renderer, Texture};
+use crate::util::{Shared, color_from_vec4, linear_to_srgb, srgb_to_linear, tonemap_aces, vec3_gl_from_color, vec4_from_color};
+
+/// 3D color lookup table, stored as `size` slices of `size` x `size` colors
+#[derive(Clone, PartialEq)]
+pub struct Lut {
+    pub size: usize,
+    pub colors: Vec<Vector3>,
+    //  file the table was read from
+    pub path: Option<String>,
+}
+
+impl Lut {
+    //  maps every color to itself
+    pub fn neutral(size: usize) -> Self {
+        let s = (size - 1) as f32;
+        let colors = (0..size * size * size).map(|i| {
+            Vector3::new((i % size) as f32, (i / size % size) as f32, (i / (size * size)) as f32) / s
+        }).collect();
+        Self { size, colors, path: None }
+    }
+
+    /// Read a LUT from the common strip layout: a `size * size` by `size`
+    /// image of blue slices left to right, red increasing to the right and
+    /// green downwards within each slice
+    pub fn from_texture(texture: &Texture) -> Result<Self, String> {
+        let (width, height) = (texture.width as usize, texture.height as usize);
+        if height < 2 || width != height * height {
+            return Err(format!("LUT must be size^2 x size pixels, got {}x{}", width, height));
+        }
+        let size = height;
+        let colors = (0..size * size * size).map(|i| {
+            let (r, g, b) = (i % size, i / size % size, i / (size * size));
+            vec3_gl_from_color(texture.buf[g * width + b * size + r])
+        }).collect();
+        Ok(Self { size, colors, path: texture.path.clone() })
+    }
+
+    fn color(&self, r: usize, g: usize, b: usize) -> Vector3 {
+        self.colors[(b * self.size + g) * self.size + r]
+    }
+
+    /// Trilinear lookup of a [0, 1] color
+    pub fn lookup(&self, c: Vector3) -> Vector3 {
+        let p = c.clamp(Vector3::ZERO, Vector3::ONE) * (self.size - 1) as f32;
+        let lo = p.floor();
+        let t = p - lo;
+        let i0 = [lo.x as usize, lo.y as usize, lo.z as usize];
+        let i1 = i0.map(|i| (i + 1).min(self.size - 1));
+
+        let lerp_r = |g: usize, b: usize| Vector3::lerp(self.color(i0[0], g, b), self.color(i1[0], g, b), t.x);
+        let lerp_g = |b: usize| Vector3::lerp(lerp_r(i0[1], b), lerp_r(i1[1], b), t.y);
+        Vector3::lerp(lerp_g(i0[2]), lerp_g(i1[2]), t.z)
+    }
+}
+
+/// Shape of out of focus highlights, the aperture of the lens
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bokeh {
+    Circle,
+    //  regular polygon with one side per aperture blade
+    Polygon { blades: u32 },
+}
+
+impl Bokeh {
+    //  distance from the center to the edge of the unit shape at an angle
+    fn radius(&self, angle: f32) -> f32 {
+        match *self {
+            Bokeh::Circle => 1.0,
+            Bokeh::Polygon { blades } => {
+                let sector = 2.0 * PI / blades.max(3) as f32;
+                (sector / 2.0).cos() / (angle.rem_euclid(sector) - sector / 2.0).cos()
+            }
+        }
+    }
+
+    //  offsets filling the unit shape in concentric rings, the first at the center
+    fn kernel(&self) -> Vec<Vector2> {
+        let mut offsets = vec![Vector2::ZERO];
+        for ring in 1..=DOF_RINGS {
+            let r = ring as f32 / DOF_RINGS as f32;
+            let count = ring * 8;
+            for i in 0..count {
+                let angle = (i as f32 + 0.5 * (ring % 2) as f32) / count as f32 * 2.0 * PI;
+                offsets.push(Vector2::new(angle.cos(), angle.sin()) * r * self.radius(angle));
+            }
+        }
+        offsets
+    }
+}
+
+//  rings of the depth of field gather kernel
+const DOF_RINGS: u32 = 4;
+//  largest circle of confusion radius in pixels
+const DOF_MAX_COC: f32 = 16.0;
+
+/// An effect applied to the finished frame
+#[derive(Clone, PartialEq)]
+pub enum Effect {
+    /// Gaussian blur with standard deviation `sigma` in pixels
+    Blur { sigma: f32 },
+    /// Glow around radiance above `threshold`.  HDR radiance is recovered by
+    /// inverting the tonemapping of frames the PBR shader or deferred
+    /// lighting drew, other shaders clip at 1 so only a threshold below 1
+    /// makes them glow.
+    Bloom { threshold: f32, intensity: f32, sigma: f32 },
+    /// Fast approximate anti-aliasing
+    Fxaa,
+    /// Darkening towards the corners, starting at `radius` from the center
+    /// (1 reaches the middle of the edges)
+    Vignette { strength: f32, radius: f32 },
+    /// Color grading through a lookup table, blended in by `strength`
+    ColorGrade { lut: Shared<Lut>, strength: f32 },
+    /// Thin lens depth of field: surfaces blur with their distance from the
+    /// focal plane, the circle of confusion growing with the aperture, the
+    /// lens diameter in world units
+    DepthOfField { focal_distance: f32, aperture: f32, bokeh: Bokeh },
+    /// Sobel edge detection on depth and, weighted by `normal_weight`,
+    /// G-buffer normals, drawn in `color`
+    Outline { threshold: f32, normal_weight: f32, color: Vector3 },
+}
+
+impl Effect {
+    //  each effect with its default parameters
+    pub fn defaults() -> [Effect; 7] {
+        [
+            Effect::Blur { sigma: 1.5 },
+            Effect::Bloom { threshold: 1.0, intensity: 0.5, sigma: 8.0 },
+            Effect::Fxaa,
+            Effect::Vignette { strength: 0.5, radius: 0.6 },
+            Effect::ColorGrade { lut: Lut::neutral(16).into(), strength: 1.0 },
+            Effect::DepthOfField { focal_distance: 3.3, aperture: 0.05, bokeh: Bokeh::Circle },
+            Effect::Outline { threshold: 0.5, normal_weight: 0.25, color: Vector3::ZERO },
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Blur { .. } => "Blur",
+            Effect::Bloom { .. } => "Bloom",
+            Effect::Fxaa => "FXAA",
+            Effect::Vignette { .. } => "Vignette",
+            Effect::ColorGrade { .. } => "Color grade",
+            Effect::DepthOfField { .. } => "Depth of field",
+            Effect::Outline { .. } => "Outline",
+        }
+    }
+}
+
+/// An entry of the post-process chain, which runs the enabled effects in order
+#[derive(Clone, PartialEq)]
+pub struct PostEffect {
+    pub enabled: bool,
+    pub effect: Effect,
+}
+
+impl PostEffect {
+    pub fn new(effect: Effect) -> Self {
+        Self { enabled: true, effect }
+    }
+}
+
+//  the frame as sRGB [0, 1] colors, row 0 at the top like `Renderer::buf`
+struct Image {
+    width: i32,
+    height: i32,
+    pixels: Vec<Vector3>,
+    //  whether the pixels are HDR radiance tonemapped with `tonemap_aces`
+    tonemapped: bool,
+}
+
+impl Image {
+    fn get(&self, x: i32, y: i32) -> Vector3 {
+        let (x, y) = (x.clamp(0, self.width - 1), y.clamp(0, self.height - 1));
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn sample(&self, p: Vector2) -> Vector3 {
+        let (x, y) = (p.x.floor(), p.y.floor());
+        let (sx, sy) = (p.x - x, p.y - y);
+        let (x, y) = (x as i32, y as i32);
+        let top = Vector3::lerp(self.get(x, y), self.get(x + 1, y), sx);
+        let bottom = Vector3::lerp(self.get(x, y + 1), self.get(x + 1, y + 1), sx);
+        Vector3::lerp(top, bottom, sy)
+    }
+
+    fn map(&self, f: impl Fn(i32, i32, Vector3) -> Vector3) -> Vec<Vector3> {
+        self.pixels.iter().enumerate()
+            .map(|(i, c)| f(i as i32 % self.width, i as i32 / self.width, *c))
+            .collect()
+    }
+}
+
+fn luma(c: Vector3) -> f32 {
+    Vector3::dot(c, Vector3::new(0.299, 0.587, 0.114))
+}
+
+//  separable Gaussian blur, clamping at the edges
+fn gaussian_blur(pixels: &[Vector3], width: i32, height: i32, sigma: f32) -> Vec<Vector3> {
+    if sigma <= 0.0 {
+        return pixels.to_vec();
+    }
+    let radius = (sigma * 3.0).ceil() as i32;
+    let weights: Vec<f32> = (-radius..=radius).map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+    let total: f32 = weights.iter().sum();
+
+    let pass = |src: &[Vector3], dx: i32, dy: i32| -> Vec<Vector3> {
+        (0..width * height).map(|i| {
+            let (x, y) = (i % width, i / width);
+            (-radius..=radius).zip(weights.iter()).map(|(o, w)| {
+                let (sx, sy) = ((x + o * dx).clamp(0, width - 1), (y + o * dy).clamp(0, height - 1));
+                src[(sy * width + sx) as usize] * *w
+            }).fold(Vector3::ZERO, |l, r| l + r) / total
+        }).collect()
+    };
+    pass(&pass(pixels, 1, 0), 0, 1)
+}
+
+//  inverse of the ACES curve of `tonemap_aces`, saturated colors map to about 5.5
+fn inverse_aces(y: Vector3) -> Vector3 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    let f = |y: f32| {
+        let y = y.clamp(0.0, 0.99);
+        let (qa, qb, qc) = (a - y * c, b - y * d, -y * e);
+        (-qb + (qb * qb - 4.0 * qa * qc).sqrt()) / (2.0 * qa)
+    };
+    Vector3::new(f(y.x), f(y.y), f(y.z))
+}
+
+const FXAA_SPAN_MAX: f32 = 8.0;
+const FXAA_REDUCE_MUL: f32 = 1.0 / 8.0;
+const FXAA_REDUCE_MIN: f32 = 1.0 / 128.0;
+
+impl Renderer {
+    /// Run the enabled effects of the chain over the frame in order, drawn
+    /// from `camera` and tonemapped from HDR if `tonemapped`
+    pub fn post_process(&mut self, effects: &[PostEffect], camera: &Camera, tonemapped: bool) {
+        if !effects.iter().any(|e| e.enabled) {
+            return;
+        }
+        let mut image = Image {
+            width: self.width,
+            height: self.height,
+            pixels: self.buf.iter().map(|c| vec3_gl_from_color(*c)).collect(),
+            tonemapped,
+        };
+
+        for effect in effects.iter().filter(|e| e.enabled) {
+            image.pixels = self.apply_effect(&image, &effect.effect, camera);
+        }
+
+        for (pixel, c) in self.buf.iter_mut().zip(image.pixels.iter()) {
+            let alpha = vec4_from_color(*pixel).w;
+            *pixel = color_from_vec4((c.clamp(Vector3::ZERO, Vector3::ONE) * 255.0).extend(alpha));
+        }
+    }
+
+    fn apply_effect(&self, image: &Image, effect: &Effect, camera: &Camera) -> Vec<Vector3> {
+        let (width, height) = (image.width, image.height);
+        match effect {
+            Effect::Blur { sigma } => gaussian_blur(&image.pixels, width, height, *sigma),
+            Effect::Bloom { threshold, intensity, sigma } => {
+                let linear = |c: &Vector3| {
+                    let l = srgb_to_linear(*c);
+                    if image.tonemapped { inverse_aces(l) } else { l }
+                };
+                let hdr: Vec<Vector3> = image.pixels.iter().map(linear).collect();
+                let bright: Vec<Vector3> = hdr.iter().map(|c| (*c - Vector3::splat(*threshold)).max(Vector3::ZERO)).collect();
+                let glow = gaussian_blur(&bright, width, height, *sigma);
+                image.pixels.iter().zip(hdr.iter().zip(glow.iter())).map(|(c, (h, g))| {
+                    //  leave pixels without glow untouched rather than round-tripping them
+                    if *g == Vector3::ZERO {
+                        *c
+                    } else if image.tonemapped {
+                        linear_to_srgb(tonemap_aces(*h + *g * *intensity))
+                    } else {
+                        linear_to_srgb((*h + *g * *intensity).min(Vector3::ONE))
+                    }
+                }).collect()
+            }
+            Effect::Fxaa => image.map(|x, y, m| {
+                let l = |dx, dy| luma(image.get(x + dx, y + dy));
+                let (nw, ne, sw, se, lm) = (l(-1, -1), l(1, -1), l(-1, 1), l(1, 1), luma(m));
+                let (luma_min, luma_max) = (lm.min(nw.min(ne).min(sw.min(se))), lm.max(nw.max(ne).max(sw.max(se))));
+
+                let dir = Vector2::new(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+                let reduce = ((nw + ne + sw + se) * 0.25 * FXAA_REDUCE_MUL).max(FXAA_REDUCE_MIN);
+                let rcp_dir_min = 1.0 / (dir.x.abs().min(dir.y.abs()) + reduce);
+                let dir = (dir * rcp_dir_min).clamp(Vector2::splat(-FXAA_SPAN_MAX), Vector2::splat(FXAA_SPAN_MAX));
+
+                let p = Vector2::new(x as f32, y as f32);
+                let a = (image.sample(p + dir * (1.0 / 3.0 - 0.5)) + image.sample(p + dir * (2.0 / 3.0 - 0.5))) * 0.5;
+                let b = a * 0.5 + (image.sample(p - dir * 0.5) + image.sample(p + dir * 0.5)) * 0.25;
+                let lb = luma(b);
+                if lb < luma_min || lb > luma_max { a } else { b }
+            }),
+            Effect::Vignette { strength, radius } => {
+                let center = Vector2::new(width as f32, height as f32) * 0.5;
+                image.map(|x, y, c| {
+                    let d = ((Vector2::new(x as f32 + 0.5, y as f32 + 0.5) - center) / center).length();
+                    let t = ((d - radius) / (std::f32::consts::SQRT_2 - radius).max(1.0e-3)).clamp(0.0, 1.0);
+                    c * (1.0 - strength * t * t * (3.0 - 2.0 * t))
+                })
+            }
+            Effect::ColorGrade { lut, strength } => image.map(|_, _, c| Vector3::lerp(c, lut.lookup(c), *strength)),
+            Effect::DepthOfField { focal_distance, aperture, bokeh } => {
+                //  blur sizes are scaled as at the camera's center, where w is
+                //  1 and the viewport maps a unit to half its size, 3/8 of the
+                //  shorter frame side for `default_viewport`
+                let pixels_per_unit = self.viewport.x_axis.x;
+                let coc: Vec<f32> = self.linear_depth(camera).iter().map(|d| {
+                    let blur = if d.is_finite() { (d - focal_distance).abs() / d.max(1.0e-3) } else { 1.0 };
+                    (aperture * pixels_per_unit * blur).min(DOF_MAX_COC)
+                }).collect();
+                let kernel = bokeh.kernel();
+
+                image.map(|x, y, c| {
+                    let radius = coc[(y * width + x) as usize];
+                    if radius < 0.5 {
+                        return c;
+                    }
+                    //  gather neighbours whose own blur reaches this pixel, so
+                    //  sharp surfaces don't bleed into their surroundings
+                    let (mut sum, mut weight) = (c, 1.0);
+                    for offset in kernel.iter().skip(1) {
+                        let o = *offset * radius;
+                        let (sx, sy) = ((x as f32 + o.x).round() as i32, (y as f32 + o.y).round() as i32);
+                        let (sx, sy) = (sx.clamp(0, width - 1), sy.clamp(0, height - 1));
+                        if coc[(sy * width + sx) as usize] >= o.length() {
+                            sum += image.get(sx, sy);
+                            weight += 1.0;
+                        }
+                    }
+                    sum / weight
+                })
+            }
+            Effect::Outline { threshold, normal_weight, color } => {
+                let depth = |x: i32, y: i32| self.zbuf[(y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize];
+                let normal = |x: i32, y: i32| {
+                    let i = (y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize;
+                    self.gbuffer.normal[i]
+                };
+                image.map(|x, y, c| {
+                    //  depth is scaled to [0, 1] so both edges share the threshold
+                    let d = sobel(|dx, dy| Vector3::splat(depth(x + dx, y + dy) / 255.0)).x * 8.0;
+                    let n = sobel(|dx, dy| normal(x + dx, y + dy)).length() * normal_weight;
+                    if d.max(n) > *threshold { *color } else { c }
+                })
+            }
+        }
+    }
+}
+
+//  gradient magnitude of each channel with the Sobel operator
+fn sobel(f: impl Fn(i32, i32) -> Vector3) -> Vector3 {
+    let gx = (f(1, -1) + f(1, 0) * 2.0 + f(1, 1)) - (f(-1, -1) + f(-1, 0) * 2.0 + f(-1, 1));
+    let gy = (f(-1, 1) + f(0, 1) * 2.0 + f(1, 1)) - (f(-1, -1) + f(0, -1) * 2.0 + f(1, -1));
+    let g = gx * gx + gy * gy;
+    Vector3::new(g.x.sqrt(), g.y.sqrt(), g.z.sqrt())
+}
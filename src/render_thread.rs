@@ -0,0 +1,126 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::renderer::{Renderer, RendererState};
+
+//  longest the displayed frame is left behind the requests by cancelling,
+//  after that the frame being drawn is finished
+const MAX_STALL: Duration = Duration::from_millis(200);
+
+//  a frame to draw, cancelled by setting `cancel`
+struct Job {
+    id: u64,
+    state: RendererState,
+    width: i32,
+    height: i32,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Draws frames on a worker thread into a back buffer.  A request cancels
+/// the frame in progress, so only the latest state is finished, unless no
+/// frame was handed back for a while, as while dragging the camera, when the
+/// frame in progress is finished first.  Finished frames are handed back as
+/// whole `Renderer`s to display.
+pub struct RenderThread {
+    jobs: Sender<Job>,
+    frames: Receiver<(u64, Renderer)>,
+    //  displayed frames given back to draw the next ones into
+    spares: Sender<Renderer>,
+    cancel: Arc<AtomicBool>,
+    //  id of the latest request
+    requested: u64,
+    //  since when the latest request has been waiting for its frame
+    waiting_since: Option<Instant>,
+}
+
+impl RenderThread {
+    pub fn new() -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (frame_sender, frames) = mpsc::channel();
+        let (spares, spare_receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("render".to_string())
+            .spawn(move || work(job_receiver, frame_sender, spare_receiver))
+            .expect("spawning the render thread failed");
+
+        Self {
+            jobs,
+            frames,
+            spares,
+            cancel: Arc::new(AtomicBool::new(false)),
+            requested: 0,
+            waiting_since: None,
+        }
+    }
+
+    /// Draw `state` in a frame of `width` by `height`, cancelling the frame
+    /// being drawn unless the display has waited too long for one.  Fails
+    /// when the render thread stopped.
+    pub fn request(&mut self, state: RendererState, width: i32, height: i32) -> Result<(), String> {
+        let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+        if waiting_since.elapsed() < MAX_STALL {
+            self.cancel.store(true, Ordering::Relaxed);
+        }
+        self.cancel = Arc::new(AtomicBool::new(false));
+        self.requested += 1;
+        let job = Job { id: self.requested, state, width, height, cancel: self.cancel.clone() };
+        //  the worker only stops when this is dropped, or when it panicked
+        self.jobs.send(job).map_err(|_| "the render thread stopped".to_string())
+    }
+
+    /// The latest finished frame, if one finished since the last call
+    pub fn try_frame(&mut self) -> Option<Renderer> {
+        let (id, frame) = self.frames.try_iter().last()?;
+        //  an older frame restarts the wait for the latest one
+        self.waiting_since = (id != self.requested).then(Instant::now);
+        Some(frame)
+    }
+
+    /// Give back a frame that is no longer displayed, to reuse its buffers
+    pub fn recycle(&self, renderer: Renderer) {
+        let _ = self.spares.send(renderer);
+    }
+}
+
+impl Default for RenderThread {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn work(jobs: Receiver<Job>, frames: Sender<(u64, Renderer)>, spares: Receiver<Renderer>) {
+    //  the buffers of a cancelled frame are drawn into again
+    let mut cancelled = None;
+    while let Ok(mut job) = jobs.recv() {
+        //  skip to the latest request
+        loop {
+            match jobs.try_recv() {
+                Ok(next) => job = next,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        if job.cancel.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let mut renderer = cancelled.take()
+            .or_else(|| spares.try_recv().ok())
+            .unwrap_or_else(|| Renderer::new(job.width, job.height));
+        renderer.resize(job.width, job.height);
+        renderer.clear();
+        renderer.cancel = Some(job.cancel);
+        renderer.draw_mesh_shader(&job.state);
+
+        let finished = !renderer.cancelled();
+        renderer.cancel = None;
+        if !finished {
+            cancelled = Some(renderer);
+        } else if frames.send((job.id, renderer)).is_err() {
+            return;
+        }
+    }
+}
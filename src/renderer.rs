@@ -1,141 +1,393 @@
 use std::{cmp, mem};
-use glam::{Vec4Swizzles};
+use std::f32::consts::PI;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use glam::{EulerRot, Quat, Vec4Swizzles};
+use log::{debug, trace};
 
-use crate::geometry::{Vector2, Vector2i, Vector3, Vector4, Matrix4, barycentric2, Matrix3};
+use crate::geometry::{Vector2, Vector2i, Vector3, Vector4, Matrix4, barycentric2};
+use crate::deferred::{GBuffer, GBufferShader};
+use crate::environment::Environment;
+use crate::fog::Fog;
+use crate::light::Light;
+use crate::mesh::perpendicular;
+use crate::postprocess::{Effect, PostEffect};
+use crate::ssao::Ssao;
+use crate::stats::{RenderStats, Stage};
+use crate::shader::{
+    Fragment, Shader, ShaderKind, Uniforms, Varying,
+    FlatShader, GouraudShader, PhongShader, NormalMappedShader, ToonShader, UnlitShader, PbrShader,
+};
 
-use crate::util::{buf_index, color_from_vec4, vec4_from_color, vec3_normal_from_color, buf_index_yinvert};
+use crate::util::{Shared, buf_index, color_from_vec4, vec4_from_color, buf_index_yinvert, linear_to_srgb};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BackgroundMode {
+    Solid,
+    Gradient,
+    Environment,
+}
+
+/// What is drawn behind geometry.  Colors are sRGB in [0, 1], the
+/// environment falls back to the solid color when none is loaded.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Background {
+    pub mode: BackgroundMode,
+    pub color: Vector3,
+    //  vertical gradient from the top to the bottom of the frame
+    pub top: Vector3,
+    pub bottom: Vector3,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self {
+            mode: BackgroundMode::Solid,
+            color: Vector3::ZERO,
+            top: Vector3::new(0.45, 0.55, 0.7),
+            bottom: Vector3::new(0.1, 0.1, 0.12),
+        }
+    }
+}
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum DisplayBuffer {
     Frame,
     Depth,
+    AmbientOcclusion,
+    Position,
+    Normal,
+    Albedo,
+    Material,
 }
 
-pub struct RendererState {
-    pub display_buffer: DisplayBuffer,
+impl DisplayBuffer {
+    pub const ALL: [DisplayBuffer; 7] = [
+        DisplayBuffer::Frame,
+        DisplayBuffer::Depth,
+        DisplayBuffer::AmbientOcclusion,
+        DisplayBuffer::Position,
+        DisplayBuffer::Normal,
+        DisplayBuffer::Albedo,
+        DisplayBuffer::Material,
+    ];
 
-    pub mesh: Mesh,
-    pub diffuse: Texture,
-    pub normal: Texture,
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayBuffer::Frame => "Frame",
+            DisplayBuffer::Depth => "Depth",
+            DisplayBuffer::AmbientOcclusion => "Ambient occlusion",
+            DisplayBuffer::Position => "Position",
+            DisplayBuffer::Normal => "Normal",
+            DisplayBuffer::Albedo => "Albedo",
+            DisplayBuffer::Material => "Material",
+        }
+    }
+}
 
-    pub model: Vector3,
+/// A named viewpoint looking from `eye` at `center`
+#[derive(Clone, PartialEq)]
+pub struct Camera {
+    pub name: String,
     pub eye: Vector3,
     pub center: Vector3,
     pub up: Vector3,
-    pub light_dir: Vector3,
+}
+
+impl Camera {
+    pub fn new(name: &str, eye: Vector3, center: Vector3, up: Vector3) -> Self {
+        Self { name: name.to_string(), eye, center, up }
+    }
+
+    pub fn projection(&self) -> Matrix4 {
+        projection((self.eye - self.center).length())
+    }
+
+    //  look_at_glam only rotates, translating the center to the origin first
+    //  puts the camera at the eye, at the distance the projection expects
+    pub fn view(&self) -> Matrix4 {
+        look_at_glam(self.eye, self.center, self.up.normalize()) * Matrix4::from_translation(-self.center)
+    }
+
+    pub fn position(&self) -> Vector3 {
+        self.eye
+    }
+
+    /// Rotate the eye around the center by `yaw` degrees about up and
+    /// `pitch` degrees towards up
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        self.eye = self.center + self.turn(self.eye - self.center, yaw, pitch);
+    }
+
+    /// Turn the view direction around the eye by `yaw` degrees about up and
+    /// `pitch` degrees towards up
+    pub fn look(&mut self, yaw: f32, pitch: f32) {
+        self.center = self.eye + self.turn(self.center - self.eye, yaw, pitch);
+    }
+
+    /// Move the eye and center across the view, in units of the distance between them
+    pub fn pan(&mut self, right: f32, up: f32) {
+        let (forward, r, u) = self.basis();
+        let offset = (r * right + u * up) * forward.length();
+        self.eye += offset;
+        self.center += offset;
+    }
+
+    /// Scale the distance from the eye to the center
+    pub fn dolly(&mut self, factor: f32) {
+        let offset = (self.eye - self.center) * factor;
+        if offset.length() > MIN_CAMERA_DISTANCE {
+            self.eye = self.center + offset;
+        }
+    }
+
+    /// Move the eye and center in world units along the view direction,
+    /// to the right of it and along up
+    pub fn fly(&mut self, forward: f32, right: f32, up: f32) {
+        let (f, r, _) = self.basis();
+        let offset = f.normalize() * forward + r * right + self.up.normalize() * up;
+        self.eye += offset;
+        self.center += offset;
+    }
+
+    //  eye to center, and the unit right and up directions of the view
+    fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = self.center - self.eye;
+        let right = Vector3::cross(forward, self.up).normalize_or_zero();
+        let up = Vector3::cross(right, forward).normalize_or_zero();
+        (forward, right, up)
+    }
+
+    //  v turned around up by yaw degrees, then towards up by pitch degrees
+    //  without passing it, which would flip the view
+    fn turn(&self, v: Vector3, yaw: f32, pitch: f32) -> Vector3 {
+        let up = self.up.normalize();
+        let v = Quat::from_axis_angle(up, yaw.to_radians()) * v;
+        let mut axis = Vector3::cross(up, v).normalize_or_zero();
+        if axis == Vector3::ZERO {
+            axis = perpendicular(up);
+        }
+        //  rotating about the axis by a positive angle moves v away from up
+        let polar = Vector3::dot(v.normalize_or_zero(), up).clamp(-1.0, 1.0).acos();
+        let min = MIN_POLAR_ANGLE.to_radians();
+        let angle = (polar - pitch.to_radians()).clamp(min, PI - min) - polar;
+        Quat::from_axis_angle(axis, angle) * v
+    }
+}
+
+//  closest the eye can be dollied to the center
+const MIN_CAMERA_DISTANCE: f32 = 1.0e-2;
+//  closest in degrees the view can turn to looking along up
+const MIN_POLAR_ANGLE: f32 = 1.0;
+
+/// A mesh placed in the world with its material
+#[derive(Clone, PartialEq)]
+pub struct Object {
+    pub mesh: Shared<Mesh>,
+    pub material: Material,
+    //  translation, rotation (euler angles in degrees) and scale
+    pub position: Vector3,
     pub rotation: Vector3,
+    pub scale: Vector3,
 }
 
-pub trait Shader {
-    fn vertex(&mut self, v: Vector4, n: Vector4, uv: Vector2, tri_index: usize) -> Vector4;
-    fn fragment(&self, bar: Vector3, frag: &mut u32) -> bool;
-}
-
-// pub struct GouraudShader<'a> {
-//     pub viewport: Matrix4,
-//     pub projection: Matrix4,
-//     pub modelview: Matrix4,
-//     pub light_dir: Vector3,
-//     pub varying_intensity: [f32; 3],
-//     pub varying_uv: [Vector2; 3],
-//     pub texture: &'a Texture
-// }
-// 
-// impl Shader for GouraudShader<'_> {
-//     fn vertex(&mut self, v: Vector4, n: Vector4, uv: Vector2, tri_index: usize) -> Vector4 {
-//         let gl_vertex = self.viewport * self.projection * self.modelview * v;
-//         let intensity = f32::max(0.0, Vector3::dot(n.xyz(), self.light_dir));
-//         self.varying_intensity[tri_index] = intensity;
-//         self.varying_uv[tri_index] = uv;
-//         // println!("gl vertex {}", gl_vertex);
-//         gl_vertex
-//     }
-// 
-//     fn fragment(&self, bar: Vector3, frag: &mut u32) -> bool {
-//         let intensity: f32 = self.varying_intensity.iter().zip(bar.to_array()).map(|(intensity, bc)| intensity*bc).sum();
-//         let uv: Vector2 = self.varying_uv.iter().zip(bar.to_array())
-//             .map(|(tex, w)| *tex * w)
-//             .reduce(|l, r| l + r)
-//             .unwrap();
-//         let c = vec4_from_color(self.texture.sample_lerp(uv.x, uv.y)).xyz() * intensity;
-//         *frag = color_from_vec4(Vector4::new(c.x, c.y, c.z, 255.0));
-// 
-//         false
-//     }
-// }
-
-pub struct PhongShader<'a> {
-    pub projection: Matrix4,
-    pub modelview: Matrix4,
-    pub light_dir: Vector3,
-    pub varying_v: [Vector4; 3],
-    pub varying_n: [Vector3; 3],
-    pub varying_uv: [Vector2; 3],
-    pub ndc_tri: [Vector3; 3],
-    pub diffuse: &'a Texture,
-    pub normal: &'a Texture
-}
-
-impl Shader for PhongShader<'_> {
-    fn vertex(&mut self, v: Vector4, n: Vector4, uv: Vector2, tri_index: usize) -> Vector4 {
-        let gl_vertex = self.projection * self.modelview * v;
-        self.varying_v[tri_index] = gl_vertex;
-        self.varying_uv[tri_index] = uv;
-        self.varying_n[tri_index] = ((self.projection * self.modelview).inverse().transpose() * n).xyz();
-        self.ndc_tri[tri_index] = (gl_vertex / gl_vertex.w).xyz();
-        // println!("gl vertex {}", gl_vertex);
-        gl_vertex
-    }
-
-    fn fragment(&self, bar: Vector3, frag: &mut u32) -> bool {
-        let bn = self.varying_n.iter().zip(bar.to_array())
-            .map(|(n, w)| *n * w)
-            .reduce(|l, r| l + r)
-            .unwrap()
-            .normalize();
-
-        let uv: Vector2 = self.varying_uv.iter().zip(bar.to_array())
-            .map(|(tex, w)| *tex * w)
-            .reduce(|l, r| l + r)
-            .unwrap();
-        
-        let a = Matrix3::from_cols(self.ndc_tri[1] - self.ndc_tri[0], self.ndc_tri[2] - self.ndc_tri[0], bn).transpose();
-        let ai = a.inverse();
-        
-        let i = ai * Vector3::new(self.varying_uv[1].x - self.varying_uv[0].x, self.varying_uv[2].x - self.varying_uv[0].x, 0.0);
-        let j = ai * Vector3::new(self.varying_uv[1].y - self.varying_uv[0].y, self.varying_uv[2].y - self.varying_uv[0].y, 0.0);
+impl Object {
+    pub fn new(mesh: Mesh, material: Material) -> Self {
+        Self {
+            mesh: mesh.into(),
+            material,
+            position: Vector3::ZERO,
+            rotation: Vector3::ZERO,
+            scale: Vector3::ONE,
+        }
+    }
 
-        let b = Matrix3::from_cols(i.normalize(), j.normalize(), bn);
-        
-        // Normal map lookup + perturb
-        let n = (b * (vec3_normal_from_color(self.normal.sample_nn(uv.x, uv.y)))).normalize();
-        // let n = vec3_gl_from_color(self.normal.sample_nn(uv.x, uv.y)).normalize();
-
-        //  diffuse lighting intensity
-        // let diffuse = f32::max(0.0, Vector3::dot(bn, self.light_dir));
-        let diffuse = f32::max(0.0, Vector3::dot(n, self.light_dir));
-
-        let c = vec4_from_color(self.diffuse.sample_lerp(uv.x, uv.y)).xyz() * diffuse;
-        // let c = vec4_from_color(self.diffuse.sample_nn(uv.x, uv.y)).xyz() * diffuse;
-        // let c = vec4_from_color(self.normal.sample_lerp(uv.x, uv.y)).xyz() * diffuse;
-        // let c = Vector3::ONE * 255.0 * diffuse;
-        
-        *frag = color_from_vec4(Vector4::new(c.x, c.y, c.z, 255.0));
+    pub fn model_matrix(&self) -> Matrix4 {
+        let r = self.rotation * (PI / 180.0);
+        Matrix4::from_translation(self.position)
+            * Matrix4::from_euler(EulerRot::XYZ, r.x, r.y, r.z)
+            * Matrix4::from_scale(self.scale)
+    }
+}
+
+/// Everything drawn in a frame, see `scene` for loading and saving it
+#[derive(Clone, PartialEq)]
+pub struct RendererState {
+    pub display_buffer: DisplayBuffer,
+    pub shader: ShaderKind,
+    //  shade in a lighting pass over the G-buffer instead of with `shader`
+    pub deferred: bool,
+
+    pub objects: Vec<Object>,
+    pub cameras: Vec<Camera>,
+    //  index of the camera the frame is drawn from
+    pub active_camera: usize,
+    pub lights: Vec<Light>,
+    pub environment: Option<Environment>,
+    pub background: Background,
+    pub ssao: Ssao,
+    pub fog: Fog,
+    pub post_effects: Vec<PostEffect>,
+}
+
+impl Default for RendererState {
+    //  nothing to draw, seen from a camera at (1, 1, 3)
+    fn default() -> Self {
+        Self {
+            display_buffer: DisplayBuffer::Frame,
+            shader: ShaderKind::NormalMapped,
+            deferred: false,
+            objects: vec![],
+            cameras: vec![Camera::new("main", Vector3::new(1.0, 1.0, 3.0), Vector3::ZERO, Vector3::Y)],
+            active_camera: 0,
+            lights: vec![],
+            environment: None,
+            background: Background::default(),
+            ssao: Ssao::default(),
+            fog: Fog::default(),
+            post_effects: vec![],
+        }
+    }
+}
+
+impl RendererState {
+    pub fn camera(&self) -> &Camera {
+        &self.cameras[self.active_camera]
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.cameras[self.active_camera]
+    }
+
+    //  uniforms of the built-in shaders for an object seen from the active camera
+    pub fn uniforms<'a>(&'a self, object: &'a Object) -> Uniforms<'a> {
+        let camera = self.camera();
+        let mut uniforms = Uniforms::new(
+            camera.projection(),
+            camera.view(),
+            object.model_matrix(),
+            camera.position(),
+            &self.lights,
+            &object.material,
+        );
+        uniforms.environment = self.environment.as_ref();
+        if self.fog.enabled && self.fog.per_fragment {
+            uniforms.fog = Some(self.fog);
+        }
+        uniforms
+    }
+
+    /// Whether the frame is HDR tonemapped to sRGB, rather than clipped
+    pub fn tonemapped(&self) -> bool {
+        self.deferred || self.shader == ShaderKind::Pbr
+    }
+
+    /// Whether anything reads the G-buffer: deferred lighting, SSAO, the
+    /// outline effect or a G-buffer view
+    pub fn needs_gbuffer(&self) -> bool {
+        let gbuffer_view = matches!(self.display_buffer,
+            DisplayBuffer::Position | DisplayBuffer::Normal | DisplayBuffer::Albedo | DisplayBuffer::Material);
+        let outline = self.post_effects.iter().any(|e| e.enabled && matches!(e.effect, Effect::Outline { .. }));
+        self.deferred || self.ssao.enabled || gbuffer_view || outline
+    }
+}
+
+/// Space the normal map's normals are expressed in
+#[derive(Clone, Copy, PartialEq)]
+pub enum NormalSpace {
+    Tangent,
+    Object,
+}
+
+/// Surface textures and parameters.  The diffuse texture doubles as base
+/// color for the metallic-roughness workflow, whose textures follow glTF:
+/// roughness in G and metallic in B, occlusion in R, sRGB emissive.  Optional
+/// textures are multiplied by their factor, which is used alone when absent.
+#[derive(Clone, PartialEq)]
+pub struct Material {
+    pub diffuse: Shared<Texture>,
+    pub normal: Shared<Texture>,
+    pub normal_space: NormalSpace,
+    //  the normal map in the other space, swapped in by `set_normal_space`
+    pub alternate_normal: Option<Shared<Texture>>,
+
+    pub metallic_roughness: Option<Shared<Texture>>,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion: Option<Shared<Texture>>,
+    pub emissive: Option<Shared<Texture>>,
+    pub emissive_factor: Vector3,
+}
 
-        false
+impl Material {
+    pub fn new(diffuse: Texture, normal: Texture, normal_space: NormalSpace) -> Self {
+        Self {
+            diffuse: diffuse.into(),
+            normal: normal.into(),
+            normal_space,
+            alternate_normal: None,
+            metallic_roughness: None,
+            metallic: 0.0,
+            roughness: 0.6,
+            occlusion: None,
+            emissive: None,
+            emissive_factor: Vector3::ZERO,
+        }
+    }
+
+    /// Switch the normal map to `space`, swapping in the alternate normal map
+    /// when there is one, otherwise the normal map is taken to be in `space`
+    pub fn set_normal_space(&mut self, space: NormalSpace) {
+        if space == self.normal_space {
+            return;
+        }
+        if let Some(alternate) = &mut self.alternate_normal {
+            mem::swap(&mut self.normal, alternate);
+        }
+        self.normal_space = space;
+    }
+
+    /// Bind a metallic-roughness texture, as in glTF its factors default to 1
+    /// so the texture is used as is
+    pub fn set_metallic_roughness(&mut self, texture: Texture) {
+        self.metallic_roughness = Some(texture.into());
+        self.metallic = 1.0;
+        self.roughness = 1.0;
     }
 }
 
+impl Default for Material {
+    //  white, with a flat tangent space normal map
+    fn default() -> Self {
+        Self::new(Texture::solid(0xffffffff), Texture::solid(0x8080ffff), NormalSpace::Tangent)
+    }
+}
 
+#[derive(Clone)]
 pub struct Texture {
     pub width: f32,
     pub height: f32,
-    pub buf: Vec<u32>
+    pub buf: Vec<u32>,
+    //  file the texture was loaded from
+    pub path: Option<String>,
 }
 
 impl Texture {
+    //  1x1 texture of a single color, not backed by a file
+    pub fn solid(color: u32) -> Self {
+        Self {
+            width: 1.0,
+            height: 1.0,
+            buf: vec![color],
+            path: None,
+        }
+    }
+
+    //  texel coordinates are clamped to the edges
     pub fn lookup(&self, x: f32, y: f32) -> u32 {
-        let index = buf_index(x, y, self.width);
+        let index = buf_index(x.clamp(0.0, self.width - 1.0), y.clamp(0.0, self.height - 1.0), self.width);
         self.buf[index]
     }
     
@@ -167,15 +419,17 @@ impl Texture {
     }
 
     pub fn log_debug(&self) {
-        println!("Texture width {} height {} buf<u32> len {}", self.width, self.height, self.buf.len());
+        trace!("texture {} width {} height {} buf<u32> len {}", self.path.as_deref().unwrap_or("-"), self.width, self.height, self.buf.len());
     }
 }
 
+#[derive(Clone)]
 pub struct Index {
     //  Indexes
     pub vertex: usize,
     pub tex: usize,
-    pub normal: usize
+    pub normal: usize,
+    pub tangent: usize,
 }
 
 impl Index {
@@ -183,11 +437,13 @@ impl Index {
         Self {
             vertex,
             tex,
-            normal
+            normal,
+            tangent: 0,
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Face {
     pub points: [Index; 3]
 }
@@ -200,6 +456,7 @@ impl Face {
     }
 }
 
+#[derive(Clone)]
 pub struct Mesh {
     //  vertices
     pub vs: Vec<Vector3>,
@@ -213,7 +470,39 @@ pub struct Mesh {
     pub ns: Vec<Vector3>,
     //  normal indices
     pub nis: Vec<i32>,
+    //  tangents with bitangent handedness in w, see `generate_tangents`
+    pub ts: Vec<Vector4>,
     pub indexes: Vec<Index>,
+    //  file the mesh was loaded from
+    pub path: Option<String>,
+}
+
+/// Standard per-vertex attributes of a `Mesh`
+#[derive(Clone, Copy)]
+pub struct Vertex {
+    pub position: Vector3,
+    pub normal: Vector3,
+    pub tangent: Vector4,
+    pub uv: Vector2,
+}
+
+impl Mesh {
+    pub fn vertex(&self, index: &Index) -> Vertex {
+        Vertex {
+            position: self.vs[index.vertex],
+            normal: self.ns[index.normal],
+            tangent: self.ts[index.tangent],
+            uv: self.tex[index.tex],
+        }
+    }
+
+    //  triangles of the index range, e.g. `mesh.triangles(..)` for the whole mesh
+    pub fn triangles(&self, indexes: impl RangeBounds<usize>) -> impl Iterator<Item = [Vertex; 3]> + '_ {
+        let range = (indexes.start_bound().cloned(), indexes.end_bound().cloned());
+        self.indexes[range].chunks_exact(3).map(move |tri_indexes| {
+            [self.vertex(&tri_indexes[0]), self.vertex(&tri_indexes[1]), self.vertex(&tri_indexes[2])]
+        })
+    }
 }
 
 pub struct Renderer {
@@ -221,10 +510,35 @@ pub struct Renderer {
     pub height: i32,
     pub buf: Vec<u32>,
     pub zbuf: Vec<f32>,
+    //  surfaces written by the shaders, see `Shader::surface`
+    pub gbuffer: GBuffer,
+    //  ambient occlusion of each pixel, see `compute_ssao`
+    pub ao: Vec<f32>,
     pub viewport: Matrix4,
+    //  set from another thread to stop drawing the frame, see `cancelled`
+    pub cancel: Option<Arc<AtomicBool>>,
+    pub stats: RenderStats,
+    //  whether shaders write their surfaces to the G-buffer, see
+    //  `RendererState::needs_gbuffer`
+    pub write_gbuffer: bool,
+    //  fragments of the triangle being drawn that passed the depth test
+    covered: Vec<Covered>,
+}
+
+//  a pixel covered by a triangle, shaded once the triangle is rasterized
+struct Covered {
+    x: i32,
+    y: i32,
+    zindex: usize,
+    depth: f32,
+    bc_clip: Vector3,
 }
 
 const DEPTH: f32 = 255.0;
+//  triangles drawn between checks for a cancelled frame
+const CANCEL_INTERVAL: usize = 256;
+//  vertices are clipped against the plane w = NEAR_W before the perspective divide
+const NEAR_W: f32 = 1.0e-3;
 
 pub fn viewport(x: f32, y: f32, w: f32, h: f32) -> Matrix4 {
     let mut m = Matrix4::IDENTITY;
@@ -240,6 +554,16 @@ pub fn viewport(x: f32, y: f32, w: f32, h: f32) -> Matrix4 {
     m
 }
 
+//  a square over 3/4 of the shorter side, centered, so the projection keeps
+//  its aspect ratio in frames of any shape
+pub fn default_viewport(width: i32, height: i32) -> Matrix4 {
+    let size = cmp::min(width, height) as f32 * 3.0/4.0;
+    viewport(
+        (width as f32 - size) / 2.0, (height as f32 - size) / 2.0,
+        size, size
+    )
+}
+
 pub fn look_at(eye: Vector3, center: Vector3, up: Vector3) -> Matrix4 {
     let z = (eye-center).normalize();
     let x = Vector3::cross(up,z).normalize();
@@ -263,6 +587,41 @@ pub fn projection(z: f32) -> Matrix4 {
     m
 }
 
+//  world position of a pixel from its window coordinates and depth, given
+//  the inverse of the viewport, projection and view transform.  The zbuf
+//  holds depth before the divide by w, so the screen position is (x w, y w,
+//  depth, w), with the w that the inverse maps to a point with w 1.
+pub(crate) fn unproject(screen_to_world: Matrix4, x: i32, y: i32, depth: f32) -> Vector3 {
+    let (x, y) = (x as f32, y as f32);
+    let r = screen_to_world.row(3);
+    let w = (1.0 - r.z * depth) / (r.x * x + r.y * y + r.w);
+    (screen_to_world * Vector4::new(x * w, y * w, depth, w)).xyz()
+}
+
+//  Sutherland-Hodgman clip of a triangle against the near plane in clip space,
+//  returns a polygon of up to 4 vertices and its length
+pub fn clip_near<V: Varying>(tri: [(Vector4, V); 3]) -> ([(Vector4, V); 4], usize) {
+    let mut out = [tri[0]; 4];
+    let mut len = 0;
+
+    for i in 0..3 {
+        let (a, b) = (tri[i], tri[(i + 1) % 3]);
+        let (da, db) = (a.0.w - NEAR_W, b.0.w - NEAR_W);
+
+        if da >= 0.0 {
+            out[len] = a;
+            len += 1;
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            let t = da / (da - db);
+            out[len] = (Vector4::lerp(a.0, b.0, t), a.1.lerp(b.1, t));
+            len += 1;
+        }
+    }
+
+    (out, len)
+}
+
 impl Renderer {
     pub fn new(width: i32, height: i32) -> Self {
         Self {
@@ -270,10 +629,30 @@ impl Renderer {
             height,
             buf: vec![0x000000ff; (width * height) as usize],
             zbuf: vec![0.0; (width * height) as usize],
-            viewport: Matrix4::IDENTITY
+            gbuffer: GBuffer::new((width * height) as usize),
+            ao: vec![1.0; (width * height) as usize],
+            viewport: default_viewport(width, height),
+            cancel: None,
+            stats: RenderStats::default(),
+            write_gbuffer: true,
+            covered: vec![],
         }
     }
     
+    /// Whether the frame being drawn was cancelled, drawing stops between
+    /// passes and every few triangles and leaves the frame unfinished
+    pub fn cancelled(&self) -> bool {
+        matches!(&self.cancel, Some(cancel) if cancel.load(Ordering::Relaxed))
+    }
+
+    /// Reallocate the buffers for a frame of `width` by `height`, they are
+    /// left cleared
+    pub fn resize(&mut self, width: i32, height: i32) {
+        if width != self.width || height != self.height {
+            *self = Self::new(width, height);
+        }
+    }
+
     pub fn clear(&mut self) {
         for pixel in &mut self.buf {
             *pixel = 0xff;
@@ -281,8 +660,52 @@ impl Renderer {
         for z in &mut self.zbuf {
             *z = 0.0;
         }
+        self.gbuffer.clear();
+        self.ao.fill(1.0);
     }
     
+    /// Fill the frame with the background, environments are looked up along
+    /// the camera ray through each pixel
+    pub fn draw_background(&mut self, background: &Background, camera: &Camera, environment: Option<&Environment>) {
+        let opaque = |c: Vector3| color_from_vec4((c.clamp(Vector3::ZERO, Vector3::ONE) * 255.0).extend(255.0));
+
+        match (background.mode, environment) {
+            (BackgroundMode::Environment, Some(environment)) => {
+                let world_to_clip = camera.projection() * camera.view();
+                let screen_to_world = self.screen_to_world(camera);
+                let eye = camera.position();
+                for (i, pixel) in self.buf.iter_mut().enumerate() {
+                    let x = (i as i32 % self.width) as f32 + 0.5;
+                    let y = (self.height - 1 - i as i32 / self.width) as f32 + 0.5;
+                    let p = screen_to_world * Vector4::new(x, y, 0.0, 1.0);
+                    let mut dir = p.xyz() / p.w - eye;
+                    //  the unprojected point may lie behind the camera
+                    if (world_to_clip * p.xyz().extend(1.0)).w < 0.0 {
+                        dir = -dir;
+                    }
+                    *pixel = opaque(linear_to_srgb(environment.background(dir)));
+                }
+            }
+            (BackgroundMode::Gradient, _) => {
+                let width = self.width as usize;
+                for (row, pixels) in self.buf.chunks_exact_mut(width).enumerate() {
+                    let t = row as f32 / (self.height - 1).max(1) as f32;
+                    pixels.fill(opaque(Vector3::lerp(background.top, background.bottom, t)));
+                }
+            }
+            _ => self.buf.fill(opaque(background.color)),
+        }
+    }
+
+    //  transform from world space to window coordinates and depth
+    pub fn world_to_screen(&self, camera: &Camera) -> Matrix4 {
+        self.viewport * camera.projection() * camera.view()
+    }
+
+    pub fn screen_to_world(&self, camera: &Camera) -> Matrix4 {
+        self.world_to_screen(camera).inverse()
+    }
+
     pub fn pixel(&mut self, x: i32, y: i32, color: u32) {
         //  clip pixels outside viewport
         if x < 0 || x >= self.width || y < 0 || y > self.height {
@@ -331,66 +754,134 @@ impl Renderer {
     }
 
     pub fn draw_mesh_shader(&mut self, renderer_state: &RendererState) {
+        let start = Instant::now();
+        self.stats = RenderStats::default();
+        self.draw_passes(renderer_state);
+        self.stats.total = start.elapsed();
+
+        if self.cancelled() {
+            debug!("frame cancelled after {:.1} ms", self.stats.total.as_secs_f64() * 1000.0);
+        } else {
+            debug!("frame {}x{} in {:.1} ms, {} triangles, {} fragments shaded",
+                self.width, self.height, self.stats.total.as_secs_f64() * 1000.0, self.stats.triangles, self.stats.shaded);
+        }
+    }
+
+    //  the background, objects and full screen passes of a frame
+    fn draw_passes(&mut self, renderer_state: &RendererState) {
         let RendererState {
-            eye,
-            center,
-            up,
-            light_dir,
-            ..
-        } = *renderer_state;
-        let RendererState {
-            mesh,
-            diffuse,
-            normal,
+            shader,
+            objects,
             ..
         } = renderer_state;
-        
-        self.viewport = viewport(
-            self.width as f32 / 8.0, self.height as f32 / 8.0,
-            self.width as f32 * 3.0/4.0, self.height as f32 * 3.0/4.0
-        );
-
-        let mut shader = PhongShader{
-            projection: projection((eye - center).length()),
-            modelview: look_at_glam(eye, center, up.normalize()),
-            light_dir: light_dir.normalize(),
-            varying_v: [Vector4::ZERO; 3],
-            varying_n: [Vector3::ZERO; 3],
-            varying_uv: [Vector2::ZERO; 3],
-            ndc_tri: [Vector3::ZERO; 3],
-            diffuse,
-            normal,
-        };
+        let camera = renderer_state.camera();
+        self.write_gbuffer = renderer_state.needs_gbuffer();
 
-        println!("vp {}\nproj {}\nmv {}\n", self.viewport, shader.projection, shader.modelview);
-        diffuse.log_debug();
-        normal.log_debug();
+        trace!("vp {}\nproj {}\nview {}", self.viewport, camera.projection(), camera.view());
         // println!("glam mv {}", Matrix4::look_at_rh(eye, center, up));
 
-        let mut pts: [Vector4; 3] = [Vector4::ZERO; 3];
-        let mut vs: [Vector4; 3] = [Vector4::ZERO; 3];
-        let mut ns: [Vector4; 3] = [Vector4::ZERO; 3];
-        let mut uvs: [Vector2; 3] = [Vector2::ZERO; 3];
-
-        for tri_indexes in mesh.indexes.chunks_exact(3) {
-            for (i, index) in tri_indexes.iter().enumerate() {
-                let v = mesh.vs[index.vertex];
-                vs[i] = Vector4::new(v.x, v.y, v.z, 1.0);
-                let n = mesh.ns[index.normal];
-                ns[i] = Vector4::new(n.x, n.y, n.z, 0.0);
-                uvs[i] = mesh.tex[index.tex];
+        let start = Instant::now();
+        self.draw_background(&renderer_state.background, camera, renderer_state.environment.as_ref());
+        self.stats.time(Stage::Background, start);
+
+        for (i, object) in objects.iter().enumerate() {
+            if self.cancelled() {
+                return;
             }
-            
-            // project vertices into screen space points (same as shader's varying_v)
-            for i in 0..vs.len() {
-                pts[i] = shader.vertex(vs[i], ns[i], uvs[i], i);
+            let uniforms = renderer_state.uniforms(object);
+            let mesh = &object.mesh;
+            trace!("model {}", uniforms.model);
+            object.material.diffuse.log_debug();
+            object.material.normal.log_debug();
+
+            if renderer_state.deferred {
+                self.draw_mesh(mesh, &GBufferShader { uniforms, material_id: i as u32 }, ..);
+                continue;
+            }
+            match *shader {
+                ShaderKind::Flat => self.draw_mesh(mesh, &FlatShader { uniforms }, ..),
+                ShaderKind::Gouraud => self.draw_mesh(mesh, &GouraudShader { uniforms }, ..),
+                ShaderKind::Phong => self.draw_mesh(mesh, &PhongShader { uniforms }, ..),
+                ShaderKind::NormalMapped => self.draw_mesh(mesh, &NormalMappedShader { uniforms }, ..),
+                ShaderKind::Toon => self.draw_mesh(mesh, &ToonShader { uniforms, bands: 4 }, ..),
+                ShaderKind::Unlit => self.draw_mesh(mesh, &UnlitShader { uniforms }, ..),
+                ShaderKind::Pbr => self.draw_mesh(mesh, &PbrShader { uniforms, exposure: 1.0 }, ..),
             }
-            
-            self.triangle_shade(&shader, pts);
+        }
+
+        if self.cancelled() {
+            return;
+        }
+        if renderer_state.deferred {
+            //  the lighting pass only uses the camera, lights and environment of the uniforms
+            if let Some(first) = objects.first() {
+                let start = Instant::now();
+                self.shade_gbuffer(&renderer_state.uniforms(first), 1.0);
+                self.stats.time(Stage::Lighting, start);
+            }
+        }
+
+        let ssao = &renderer_state.ssao;
+        if ssao.enabled && !self.cancelled() {
+            let start = Instant::now();
+            self.compute_ssao(camera, ssao);
+            self.apply_ssao(ssao.strength);
+            self.stats.time(Stage::Ssao, start);
+        }
+
+        let fog = &renderer_state.fog;
+        if fog.enabled && !fog.per_fragment && !self.cancelled() {
+            let start = Instant::now();
+            self.apply_fog(fog, camera);
+            self.stats.time(Stage::Fog, start);
+        }
+
+        if !self.cancelled() {
+            let start = Instant::now();
+            self.post_process(&renderer_state.post_effects, camera, renderer_state.tonemapped());
+            self.stats.time(Stage::PostProcess, start);
         }
     }
 
-    pub fn triangle_shade(&mut self, shader: &impl Shader, clipc: [Vector4; 3]) {
+    /// Draw the triangles of a mesh's index range with any shader taking `Vertex` attributes
+    pub fn draw_mesh<S>(&mut self, mesh: &Mesh, shader: &S, indexes: impl RangeBounds<usize>)
+    where
+        S: Shader<Attribute = Vertex>
+    {
+        self.draw_triangles(shader, mesh.triangles(indexes));
+    }
+
+    /// Run each triangle's attributes through the vertex shader, clip it and rasterize it
+    pub fn draw_triangles<S: Shader>(&mut self, shader: &S, triangles: impl IntoIterator<Item = [S::Attribute; 3]>) {
+        for (n, attributes) in triangles.into_iter().enumerate() {
+            if n % CANCEL_INTERVAL == 0 && self.cancelled() {
+                return;
+            }
+            let start = Instant::now();
+            let vertices = attributes.map(|a| shader.vertex(&a));
+            self.stats.triangles += 1;
+            match vertices.iter().filter(|(clipc, _)| clipc.w < NEAR_W).count() {
+                0 => {}
+                3 => self.stats.behind_near += 1,
+                _ => self.stats.clipped += 1,
+            }
+            let (polygon, len) = clip_near(vertices);
+            self.stats.time(Stage::Vertex, start);
+
+            //  triangulate the clipped polygon as a fan
+            for i in 1..len.saturating_sub(1) {
+                let (clipc, varyings) = (
+                    [polygon[0].0, polygon[i].0, polygon[i + 1].0],
+                    [polygon[0].1, polygon[i].1, polygon[i + 1].1],
+                );
+                self.triangle_shade(shader, clipc, &varyings);
+            }
+        }
+    }
+
+    /// Rasterize a clipped triangle and shade the pixels it covers that pass the depth test
+    pub fn triangle_shade<S: Shader>(&mut self, shader: &S, clipc: [Vector4; 3], varyings: &[S::Varying; 3]) {
+        let start = Instant::now();
         let pts = clipc.map(|v| self.viewport * v);
         let pts2 = pts.map(|v| v.xy() / v.w);
         // println!("Triangle {} {} {}", pts[0], pts[1], pts[2]);
@@ -404,6 +895,9 @@ impl Renderer {
             bboxmax.x = cmp::min(clamp.x, cmp::max(bboxmax.x, pt.x.ceil() as i32)); 
             bboxmax.y = cmp::min(clamp.y, cmp::max(bboxmax.y, pt.y.ceil() as i32)); 
         } 
+        if bboxmin.x < bboxmax.x && bboxmin.y < bboxmax.y {
+            self.stats.rasterized += 1;
+        }
         
         for x in bboxmin.x..bboxmax.x {
             for y in bboxmin.y..bboxmax.y {
@@ -416,21 +910,51 @@ impl Renderer {
                     continue;
                 }
                 
+                self.stats.fragments += 1;
                 let frag_depth = Vector3::dot(Vector3::new(pts[0].z, pts[1].z, pts[2].z), bc_clip);
                 // println!("Frag depth {}", frag_depth);
                 let zindex = buf_index_yinvert(x, y, self.width, self.height);
                 if self.zbuf[zindex] > frag_depth {
+                    self.stats.depth_rejected += 1;
                     continue
                 }
-                
-                let mut color: u32 = 0;
-                let discard = shader.fragment(bc_clip, &mut color);
-                if !discard {
-                    self.zbuf[zindex] = frag_depth;
-                    self.pixel(x, y, color);
-                }
+                self.covered.push(Covered { x, y, zindex, depth: frag_depth, bc_clip });
+            }
+        }
+        self.stats.time(Stage::Raster, start);
+
+        //  a triangle covers each pixel once, so the depth tests above hold
+        let start = Instant::now();
+        let mut covered = mem::take(&mut self.covered);
+        for Covered { x, y, zindex, depth, bc_clip } in covered.drain(..) {
+            let frag = Fragment {
+                coord: Vector2::new(x as f32, y as f32),
+                depth,
+                varying: S::Varying::interpolate(varyings, bc_clip),
+                triangle: varyings,
+            };
+            let mut color: u32 = 0;
+            let (discard, surface) = if self.write_gbuffer {
+                shader.fragment_surface(&frag, &mut color)
+            } else {
+                (shader.fragment(&frag, &mut color), None)
+            };
+            if discard {
+                self.stats.discarded += 1;
+                continue;
+            }
+            self.stats.shaded += 1;
+            if self.zbuf[zindex] != 0.0 {
+                self.stats.overdraw += 1;
             }
+            self.zbuf[zindex] = depth;
+            if let Some(surface) = surface {
+                self.gbuffer.write(zindex, &surface);
+            }
+            self.pixel(x, y, color);
         }
+        self.covered = covered;
+        self.stats.time(Stage::Fragment, start);
     }
 
     pub fn draw(&self, frame: &mut [u8], display_buffer: DisplayBuffer) {
@@ -446,9 +970,29 @@ impl Renderer {
                 let zbuf = self.zbuf_buf();
                 draw_buf(&zbuf);
             }
+            DisplayBuffer::AmbientOcclusion => draw_buf(&self.ao_buf()),
+            DisplayBuffer::Position => draw_buf(&self.gbuffer.position_buf()),
+            DisplayBuffer::Normal => draw_buf(&self.gbuffer.normal_buf()),
+            DisplayBuffer::Albedo => draw_buf(&self.gbuffer.albedo_buf()),
+            DisplayBuffer::Material => draw_buf(&self.gbuffer.material_buf()),
         };
     }
 
+    /// Distance of each pixel's surface in front of the camera along the
+    /// view direction, infinite where nothing was drawn
+    pub fn linear_depth(&self, camera: &Camera) -> Vec<f32> {
+        let screen_to_world = self.screen_to_world(camera);
+        let (view, eye) = (camera.view(), camera.position());
+        self.zbuf.iter().enumerate().map(|(i, z)| {
+            if *z == 0.0 {
+                return f32::INFINITY;
+            }
+            let (x, y) = (i as i32 % self.width, self.height - 1 - i as i32 / self.width);
+            let p = unproject(screen_to_world, x, y, *z);
+            -view.transform_vector3(p - eye).z
+        }).collect()
+    }
+
     pub fn zbuf_buf(&self) -> Vec<u32> {
         self.zbuf.iter().map(|z| {
             let mut vc = *z * Vector4::ONE;
@@ -458,3 +1002,55 @@ impl Renderer {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(ws: [f32; 3]) -> Vec<(Vector4, f32)> {
+        let tri = ws.map(|w| (Vector4::new(w, 0.0, 0.0, w), w));
+        let (polygon, len) = clip_near(tri);
+        polygon[..len].to_vec()
+    }
+
+    #[test]
+    fn clip_near_keeps_a_triangle_in_front() {
+        assert_eq!(clip([1.0, 2.0, 3.0]).iter().map(|(_, w)| *w).collect::<Vec<_>>(), [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn clip_near_drops_a_triangle_behind() {
+        assert!(clip([-1.0, -2.0, 0.0]).is_empty());
+    }
+
+    #[test]
+    fn clip_near_cuts_a_straddling_triangle_at_the_plane() {
+        //  one vertex behind leaves a quad, two leave a triangle
+        for (ws, len) in [([1.0, 2.0, -1.0], 4), ([1.0, -2.0, -1.0], 3)] {
+            let polygon = clip(ws);
+            assert_eq!(polygon.len(), len);
+            for (p, varying) in polygon {
+                assert!(p.w >= NEAR_W - 1e-6);
+                assert!((p.x - varying).abs() < 1e-6 && (p.w - varying).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn unproject_recovers_known_points() {
+        //  a 64x64 frame has its viewport centered at (32, 32) and 48 pixels
+        //  across, and the camera looks down -z from 4 units away
+        let renderer = Renderer::new(64, 64);
+        let camera = Camera::new("main", Vector3::new(0.0, 0.0, 4.0), Vector3::ZERO, Vector3::Y);
+        let screen_to_world = renderer.screen_to_world(&camera);
+
+        //  on the center's plane w is 1 and depth is DEPTH / 2
+        let p = unproject(screen_to_world, 44, 38, DEPTH / 2.0);
+        assert!(p.abs_diff_eq(Vector3::new(0.5, 0.25, 0.0), 1e-4), "{}", p);
+
+        //  2 units towards the camera w is 1 - 2/4, x is (24 * 0.5 + 32 w) / w
+        //  and the undivided depth is DEPTH / 2 * (2 + w)
+        let p = unproject(screen_to_world, 56, 32, DEPTH / 2.0 * 2.5);
+        assert!(p.abs_diff_eq(Vector3::new(0.5, 0.0, 2.0), 1e-4), "{}", p);
+    }
+}
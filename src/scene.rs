@@ -0,0 +1,606 @@
+//! Text scene files describing what a `RendererState` draws, e.g.
+//!
+//! ```text
+//! # the african head lit from the top right
+//! render
+//!     shader normal-mapped
+//! camera main
+//!     eye 1 1 3
+//! object ../obj/african_head.obj
+//!     diffuse ../obj/african_head_diffuse.png
+//!     normal ../obj/african_head_nm_tangent.png
+//! light directional
+//!     direction 1 1 1
+//! ```
+//!
+//! Each line is a keyword followed by its values, `#` starts a comment.
+//! Block keywords (`render`, `background`, `camera`, `object`, `light`,
+//! `environment`, `ssao`, `fog` and `effect`) start an item the property
+//! lines after them set, anything left out keeps its default, except that
+//! an object's `metallic_roughness` texture resets its `metallic` and
+//! `roughness` factors to 1 so give them after it.  An object's
+//! `alternate_normal` is its normal map in the other normal space, swapped in
+//! when the space is switched.  Names and paths take the rest of the line,
+//! relative paths are relative to the directory of the scene file.
+
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::environment::Environment;
+use crate::fog::FogMode;
+use crate::geometry::Vector3;
+use crate::light::{Attenuation, Light};
+use crate::objloader::read_obj;
+use crate::postprocess::{Bokeh, Effect, Lut, PostEffect};
+use crate::renderer::{BackgroundMode, Camera, DisplayBuffer, Material, NormalSpace, Object, RendererState, Texture};
+use crate::shader::ShaderKind;
+use crate::util::read_png_texture;
+
+/// Scene the viewer starts with
+pub const DEFAULT_SCENE: &str = "scenes/african_head.scene";
+
+pub struct Scene {
+    pub state: RendererState,
+    //  image size to render at, when given
+    pub size: Option<(i32, i32)>,
+}
+
+/// Name of an enum variant in scene files and on the command line, e.g.
+/// "normal-mapped" for `ShaderKind::NormalMapped`
+pub fn name_key(name: &str) -> String {
+    name.to_lowercase().replace(' ', "-")
+}
+
+pub fn load_scene(path: &str) -> Result<Scene, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse_scene(&text, scene_dir(path)).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn save_scene(path: &str, state: &RendererState, size: Option<(i32, i32)>) -> Result<(), String> {
+    fs::write(path, write_scene(state, size, scene_dir(path))).map_err(|e| format!("{}: {}", path, e))
+}
+
+//  the directory the paths in a scene file are relative to
+fn scene_dir(path: &str) -> &Path {
+    Path::new(path).parent().unwrap_or_else(|| Path::new(""))
+}
+
+/// Parse a scene file, loading the files it names relative to `dir`
+pub fn parse_scene(text: &str, dir: &Path) -> Result<Scene, String> {
+    let mut parser = Parser {
+        dir: dir.to_path_buf(),
+        state: RendererState::default(),
+        size: None,
+        cameras: vec![],
+        active_camera: None,
+        block: None,
+    };
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (keyword, value) = match line.split_once(char::is_whitespace) {
+            Some((keyword, value)) => (keyword, value.trim()),
+            None => (line, ""),
+        };
+        parser.line(keyword, value).map_err(|e| format!("line {}: {}", number + 1, e))?;
+    }
+    parser.finish()
+}
+
+/// The scene file of a state, to be saved in `dir`.  Meshes, textures and
+/// environments that weren't loaded from a file are left out.
+pub fn write_scene(state: &RendererState, size: Option<(i32, i32)>, dir: &Path) -> String {
+    let mut out = String::new();
+    write_state(&mut out, state, size, dir).expect("writing to a String");
+    out
+}
+
+#[derive(Clone, Copy)]
+enum Block {
+    Render,
+    Background,
+    Camera,
+    Object,
+    Light,
+    Environment,
+    Ssao,
+    Fog,
+    Effect,
+}
+
+struct Parser {
+    //  directory of the scene file
+    dir: PathBuf,
+    state: RendererState,
+    size: Option<(i32, i32)>,
+    //  replace the default camera when any are given
+    cameras: Vec<Camera>,
+    active_camera: Option<String>,
+    //  block the property lines apply to
+    block: Option<Block>,
+}
+
+impl Parser {
+    fn line(&mut self, keyword: &str, value: &str) -> Result<(), String> {
+        let dir = &self.dir;
+        let state = &mut self.state;
+        let block = match keyword {
+            "render" => Block::Render,
+            "background" => {
+                state.background.mode = match value {
+                    "solid" => BackgroundMode::Solid,
+                    "gradient" => BackgroundMode::Gradient,
+                    "environment" => BackgroundMode::Environment,
+                    _ => return Err(format!("unknown background '{}'", value)),
+                };
+                Block::Background
+            }
+            "camera" => {
+                let camera = RendererState::default().camera().clone();
+                self.cameras.push(Camera { name: value.to_string(), ..camera });
+                Block::Camera
+            }
+            "object" => {
+                let path = resolve(dir, value);
+                let mesh = read_obj(&path).map_err(|e| format!("{}: {}", path, e))?;
+                state.objects.push(Object::new(mesh, Material::default()));
+                Block::Object
+            }
+            "light" => {
+                state.lights.push(match value {
+                    "directional" => Light::directional(Vector3::ONE),
+                    "point" => Light::point(Vector3::ONE),
+                    "spot" => Light::spot(Vector3::new(0.0, 0.0, 2.0), Vector3::new(0.0, 0.0, -1.0)),
+                    _ => return Err(format!("unknown light '{}'", value)),
+                });
+                Block::Light
+            }
+            "environment" => {
+                let path = resolve(dir, value);
+                state.environment = Some(Environment::load(&path).map_err(|e| format!("{}: {}", path, e))?);
+                Block::Environment
+            }
+            "ssao" => Block::Ssao,
+            "fog" => {
+                state.fog.mode = parse_key(value, &FogMode::ALL, FogMode::name)?;
+                Block::Fog
+            }
+            "effect" => {
+                let effect = Effect::defaults().into_iter()
+                    .find(|effect| name_key(effect.name()) == value)
+                    .ok_or_else(|| format!("unknown effect '{}'", value))?;
+                state.post_effects.push(PostEffect::new(effect));
+                Block::Effect
+            }
+            _ => {
+                let block = self.block.ok_or_else(|| format!("unknown keyword '{}'", keyword))?;
+                return self.property(block, keyword, value);
+            }
+        };
+        self.block = Some(block);
+        Ok(())
+    }
+
+    fn property(&mut self, block: Block, key: &str, value: &str) -> Result<(), String> {
+        let dir = &self.dir;
+        let state = &mut self.state;
+        let texture = |value: &str| texture(&resolve(dir, value));
+        match block {
+            Block::Render => match key {
+                "shader" => state.shader = parse_key(value, &ShaderKind::ALL, ShaderKind::name)?,
+                "deferred" => state.deferred = parse_bool(value)?,
+                "display" => state.display_buffer = parse_key(value, &DisplayBuffer::ALL, DisplayBuffer::name)?,
+                "size" => self.size = Some(parse_size(value)?),
+                "active_camera" => self.active_camera = Some(value.to_string()),
+                _ => return Err(unknown_property("render", key)),
+            },
+            Block::Background => {
+                let background = &mut state.background;
+                match key {
+                    "color" => background.color = parse_vec3(value)?,
+                    "top" => background.top = parse_vec3(value)?,
+                    "bottom" => background.bottom = parse_vec3(value)?,
+                    _ => return Err(unknown_property("background", key)),
+                }
+            }
+            Block::Camera => {
+                let camera = self.cameras.last_mut().unwrap();
+                match key {
+                    "eye" => camera.eye = parse_vec3(value)?,
+                    "center" => camera.center = parse_vec3(value)?,
+                    "up" => camera.up = parse_vec3(value)?,
+                    _ => return Err(unknown_property("camera", key)),
+                }
+            }
+            Block::Object => {
+                let object = state.objects.last_mut().unwrap();
+                let material = &mut object.material;
+                match key {
+                    "position" => object.position = parse_vec3(value)?,
+                    "rotation" => object.rotation = parse_vec3(value)?,
+                    "scale" => object.scale = parse_vec3(value)?,
+                    "diffuse" => material.diffuse = texture(value)?.into(),
+                    "normal" => material.normal = texture(value)?.into(),
+                    "alternate_normal" => material.alternate_normal = Some(texture(value)?.into()),
+                    "normal_space" => material.normal_space = match value {
+                        "tangent" => NormalSpace::Tangent,
+                        "object" => NormalSpace::Object,
+                        _ => return Err(format!("unknown normal space '{}'", value)),
+                    },
+                    "metallic_roughness" => material.set_metallic_roughness(texture(value)?),
+                    "metallic" => material.metallic = parse_float(value)?,
+                    "roughness" => material.roughness = parse_float(value)?,
+                    "occlusion" => material.occlusion = Some(texture(value)?.into()),
+                    "emissive" => material.emissive = Some(texture(value)?.into()),
+                    "emissive_factor" => material.emissive_factor = parse_vec3(value)?,
+                    _ => return Err(unknown_property("object", key)),
+                }
+            }
+            Block::Light => light_property(state.lights.last_mut().unwrap(), key, value)?,
+            Block::Environment => match (state.environment.as_mut(), key) {
+                (Some(environment), "intensity") => environment.intensity = parse_float(value)?,
+                _ => return Err(unknown_property("environment", key)),
+            },
+            Block::Ssao => {
+                let ssao = &mut state.ssao;
+                match key {
+                    "enabled" => ssao.enabled = parse_bool(value)?,
+                    "radius" => ssao.radius = parse_float(value)?,
+                    "samples" => ssao.samples = value.parse().map_err(|_| format!("invalid count '{}'", value))?,
+                    "strength" => ssao.strength = parse_float(value)?,
+                    "bias" => ssao.bias = parse_float(value)?,
+                    _ => return Err(unknown_property("ssao", key)),
+                }
+            }
+            Block::Fog => {
+                let fog = &mut state.fog;
+                match key {
+                    "enabled" => fog.enabled = parse_bool(value)?,
+                    "per_fragment" => fog.per_fragment = parse_bool(value)?,
+                    "color" => fog.color = parse_vec3(value)?,
+                    "density" => fog.density = parse_float(value)?,
+                    "start" => fog.start = parse_float(value)?,
+                    "end" => fog.end = parse_float(value)?,
+                    "height" => fog.height = parse_float(value)?,
+                    "falloff" => fog.falloff = parse_float(value)?,
+                    _ => return Err(unknown_property("fog", key)),
+                }
+            }
+            Block::Effect => {
+                let post_effect = state.post_effects.last_mut().unwrap();
+                match key {
+                    "enabled" => post_effect.enabled = parse_bool(value)?,
+                    _ => effect_property(&mut post_effect.effect, key, value, dir)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Scene, String> {
+        if !self.cameras.is_empty() {
+            self.state.cameras = self.cameras;
+        }
+        if let Some(name) = &self.active_camera {
+            self.state.active_camera = self.state.cameras.iter()
+                .position(|camera| camera.name == *name)
+                .ok_or_else(|| format!("no camera named '{}'", name))?;
+        }
+        Ok(Scene { state: self.state, size: self.size })
+    }
+}
+
+fn light_property(light: &mut Light, key: &str, value: &str) -> Result<(), String> {
+    match (&mut *light, key) {
+        (Light::Directional { direction, .. } | Light::Spot { direction, .. }, "direction") => *direction = parse_vec3(value)?,
+        (Light::Point { position, .. } | Light::Spot { position, .. }, "position") => *position = parse_vec3(value)?,
+        (Light::Directional { color, .. } | Light::Point { color, .. } | Light::Spot { color, .. }, "color") => {
+            *color = parse_vec3(value)?
+        }
+        (Light::Directional { intensity, .. } | Light::Point { intensity, .. } | Light::Spot { intensity, .. }, "intensity") => {
+            *intensity = parse_float(value)?
+        }
+        (Light::Point { attenuation, .. } | Light::Spot { attenuation, .. }, "attenuation") => {
+            let [constant, linear, quadratic] = parse_floats(value)?;
+            *attenuation = Attenuation::new(constant, linear, quadratic);
+        }
+        (Light::Spot { inner_angle, outer_angle, .. }, "cone") => {
+            let [inner, outer] = parse_floats(value)?;
+            *inner_angle = inner;
+            *outer_angle = outer;
+        }
+        _ => return Err(unknown_property(&format!("{} light", name_key(light.name())), key)),
+    }
+    Ok(())
+}
+
+fn effect_property(effect: &mut Effect, key: &str, value: &str, dir: &Path) -> Result<(), String> {
+    match (&mut *effect, key) {
+        (Effect::Blur { sigma } | Effect::Bloom { sigma, .. }, "sigma") => *sigma = parse_float(value)?,
+        (Effect::Bloom { threshold, .. } | Effect::Outline { threshold, .. }, "threshold") => *threshold = parse_float(value)?,
+        (Effect::Bloom { intensity, .. }, "intensity") => *intensity = parse_float(value)?,
+        (Effect::Vignette { strength, .. } | Effect::ColorGrade { strength, .. }, "strength") => *strength = parse_float(value)?,
+        (Effect::Vignette { radius, .. }, "radius") => *radius = parse_float(value)?,
+        (Effect::ColorGrade { lut, .. }, "lut") => {
+            let path = resolve(dir, value);
+            *lut = Lut::from_texture(&texture(&path)?).map_err(|e| format!("{}: {}", path, e))?.into()
+        }
+        (Effect::DepthOfField { focal_distance, .. }, "focal_distance") => *focal_distance = parse_float(value)?,
+        (Effect::DepthOfField { aperture, .. }, "aperture") => *aperture = parse_float(value)?,
+        (Effect::DepthOfField { bokeh, .. }, "bokeh") => {
+            *bokeh = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                ["circle"] => Bokeh::Circle,
+                ["polygon", blades] => Bokeh::Polygon { blades: blades.parse().map_err(|_| format!("invalid blade count '{}'", blades))? },
+                _ => return Err(format!("expected 'circle' or 'polygon BLADES', got '{}'", value)),
+            }
+        }
+        (Effect::Outline { normal_weight, .. }, "normal_weight") => *normal_weight = parse_float(value)?,
+        (Effect::Outline { color, .. }, "color") => *color = parse_vec3(value)?,
+        _ => return Err(unknown_property(&format!("{} effect", name_key(effect.name())), key)),
+    }
+    Ok(())
+}
+
+fn unknown_property(block: &str, key: &str) -> String {
+    format!("unknown {} property '{}'", block, key)
+}
+
+fn texture(path: &str) -> Result<Texture, String> {
+    read_png_texture(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+//  a path in a scene file as a path to open, with the `..` after the scene
+//  directory's own components taken out
+fn resolve(dir: &Path, path: &str) -> String {
+    let mut resolved = PathBuf::new();
+    for component in dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(resolved.components().next_back(), Some(Component::Normal(_))) => {
+                resolved.pop();
+            }
+            _ => resolved.push(component),
+        }
+    }
+    resolved.to_string_lossy().into_owned()
+}
+
+//  a path to write in a scene file in `dir`, kept as it is when either
+//  can't be found
+fn relative(dir: &Path, path: &str) -> String {
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let (dir, absolute) = match (fs::canonicalize(dir), fs::canonicalize(path)) {
+        (Ok(dir), Ok(absolute)) => (dir, absolute),
+        _ => return path.to_string(),
+    };
+    let common = dir.components().zip(absolute.components()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return path.to_string();
+    }
+    let mut relative = PathBuf::new();
+    for _ in dir.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(absolute.components().skip(common));
+    relative.to_string_lossy().into_owned()
+}
+
+fn parse_floats<const N: usize>(s: &str) -> Result<[f32; N], String> {
+    let values: Vec<f32> = s.split_whitespace()
+        .map(|v| v.parse::<f32>().map_err(|_| format!("invalid number '{}'", v)))
+        .collect::<Result<_, _>>()?;
+    values.try_into().map_err(|_| format!("expected {} numbers, got '{}'", N, s))
+}
+
+fn parse_float(s: &str) -> Result<f32, String> {
+    parse_floats::<1>(s).map(|[v]| v)
+}
+
+fn parse_vec3(s: &str) -> Result<Vector3, String> {
+    parse_floats::<3>(s).map(Vector3::from)
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+    match s {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected true or false, got '{}'", s)),
+    }
+}
+
+/// Largest width or height accepted, keeping the pixel count of a frame in an `i32`
+pub const MAX_SIZE: i32 = 16384;
+
+pub fn parse_size(s: &str) -> Result<(i32, i32), String> {
+    let (w, h) = s.split_once('x').ok_or_else(|| format!("expected WxH, got '{}'", s))?;
+    match (w.parse::<i32>(), h.parse::<i32>()) {
+        (Ok(w), Ok(h)) if (1..=MAX_SIZE).contains(&w) && (1..=MAX_SIZE).contains(&h) => Ok((w, h)),
+        (Ok(_), Ok(_)) => Err(format!("size '{}' out of range, each side must be 1 to {}", s, MAX_SIZE)),
+        _ => Err(format!("invalid size '{}'", s)),
+    }
+}
+
+//  the variant of `all` whose name has the key `s`
+fn parse_key<T: Copy>(s: &str, all: &[T], name: fn(&T) -> &'static str) -> Result<T, String> {
+    all.iter().copied()
+        .find(|v| name_key(name(v)) == s)
+        .ok_or_else(|| format!("unknown '{}', expected one of {}", s, all.iter().map(|v| name_key(name(v))).collect::<Vec<_>>().join(", ")))
+}
+
+fn vec3(v: Vector3) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+fn write_state(out: &mut String, state: &RendererState, size: Option<(i32, i32)>, dir: &Path) -> fmt::Result {
+    writeln!(out, "render")?;
+    writeln!(out, "    shader {}", name_key(state.shader.name()))?;
+    writeln!(out, "    deferred {}", state.deferred)?;
+    writeln!(out, "    display {}", name_key(state.display_buffer.name()))?;
+    if let Some((width, height)) = size {
+        writeln!(out, "    size {}x{}", width, height)?;
+    }
+    writeln!(out, "    active_camera {}", state.camera().name)?;
+
+    let background = &state.background;
+    writeln!(out, "\nbackground {}", match background.mode {
+        BackgroundMode::Solid => "solid",
+        BackgroundMode::Gradient => "gradient",
+        BackgroundMode::Environment => "environment",
+    })?;
+    writeln!(out, "    color {}", vec3(background.color))?;
+    writeln!(out, "    top {}", vec3(background.top))?;
+    writeln!(out, "    bottom {}", vec3(background.bottom))?;
+
+    for camera in &state.cameras {
+        writeln!(out, "\ncamera {}", camera.name)?;
+        writeln!(out, "    eye {}", vec3(camera.eye))?;
+        writeln!(out, "    center {}", vec3(camera.center))?;
+        writeln!(out, "    up {}", vec3(camera.up))?;
+    }
+
+    for object in &state.objects {
+        let path = match &object.mesh.path {
+            Some(path) => path,
+            None => continue,
+        };
+        writeln!(out, "\nobject {}", relative(dir, path))?;
+        writeln!(out, "    position {}", vec3(object.position))?;
+        writeln!(out, "    rotation {}", vec3(object.rotation))?;
+        writeln!(out, "    scale {}", vec3(object.scale))?;
+
+        let material = &object.material;
+        let textures = [
+            ("diffuse", Some(&material.diffuse)),
+            ("normal", Some(&material.normal)),
+            ("alternate_normal", material.alternate_normal.as_ref()),
+            ("metallic_roughness", material.metallic_roughness.as_ref()),
+            ("occlusion", material.occlusion.as_ref()),
+            ("emissive", material.emissive.as_ref()),
+        ];
+        for (key, texture) in textures {
+            if let Some(path) = texture.and_then(|t| t.path.as_ref()) {
+                writeln!(out, "    {} {}", key, relative(dir, path))?;
+            }
+        }
+        writeln!(out, "    normal_space {}", match material.normal_space {
+            NormalSpace::Tangent => "tangent",
+            NormalSpace::Object => "object",
+        })?;
+        writeln!(out, "    metallic {}", material.metallic)?;
+        writeln!(out, "    roughness {}", material.roughness)?;
+        writeln!(out, "    emissive_factor {}", vec3(material.emissive_factor))?;
+    }
+
+    for light in &state.lights {
+        writeln!(out, "\nlight {}", name_key(light.name()))?;
+        match light {
+            Light::Directional { direction, color, intensity } => {
+                writeln!(out, "    direction {}", vec3(*direction))?;
+                writeln!(out, "    color {}\n    intensity {}", vec3(*color), intensity)?;
+            }
+            Light::Point { position, color, intensity, attenuation } => {
+                writeln!(out, "    position {}", vec3(*position))?;
+                writeln!(out, "    color {}\n    intensity {}", vec3(*color), intensity)?;
+                writeln!(out, "    attenuation {} {} {}", attenuation.constant, attenuation.linear, attenuation.quadratic)?;
+            }
+            Light::Spot { position, direction, color, intensity, attenuation, inner_angle, outer_angle } => {
+                writeln!(out, "    position {}", vec3(*position))?;
+                writeln!(out, "    direction {}", vec3(*direction))?;
+                writeln!(out, "    color {}\n    intensity {}", vec3(*color), intensity)?;
+                writeln!(out, "    attenuation {} {} {}", attenuation.constant, attenuation.linear, attenuation.quadratic)?;
+                writeln!(out, "    cone {} {}", inner_angle, outer_angle)?;
+            }
+        }
+    }
+
+    if let Some(environment) = &state.environment {
+        if let Some(path) = &environment.path {
+            writeln!(out, "\nenvironment {}", relative(dir, path))?;
+            writeln!(out, "    intensity {}", environment.intensity)?;
+        }
+    }
+
+    let ssao = &state.ssao;
+    writeln!(out, "\nssao")?;
+    writeln!(out, "    enabled {}", ssao.enabled)?;
+    writeln!(out, "    radius {}\n    samples {}", ssao.radius, ssao.samples)?;
+    writeln!(out, "    strength {}\n    bias {}", ssao.strength, ssao.bias)?;
+
+    let fog = &state.fog;
+    writeln!(out, "\nfog {}", name_key(fog.mode.name()))?;
+    writeln!(out, "    enabled {}\n    per_fragment {}", fog.enabled, fog.per_fragment)?;
+    writeln!(out, "    color {}\n    density {}", vec3(fog.color), fog.density)?;
+    writeln!(out, "    start {}\n    end {}", fog.start, fog.end)?;
+    writeln!(out, "    height {}\n    falloff {}", fog.height, fog.falloff)?;
+
+    for post_effect in &state.post_effects {
+        writeln!(out, "\neffect {}", name_key(post_effect.effect.name()))?;
+        writeln!(out, "    enabled {}", post_effect.enabled)?;
+        match &post_effect.effect {
+            Effect::Blur { sigma } => writeln!(out, "    sigma {}", sigma)?,
+            Effect::Bloom { threshold, intensity, sigma } => {
+                writeln!(out, "    threshold {}\n    intensity {}\n    sigma {}", threshold, intensity, sigma)?
+            }
+            Effect::Fxaa => {}
+            Effect::Vignette { strength, radius } => writeln!(out, "    strength {}\n    radius {}", strength, radius)?,
+            Effect::ColorGrade { lut, strength } => {
+                if let Some(path) = &lut.path {
+                    writeln!(out, "    lut {}", relative(dir, path))?;
+                }
+                writeln!(out, "    strength {}", strength)?;
+            }
+            Effect::DepthOfField { focal_distance, aperture, bokeh } => {
+                writeln!(out, "    focal_distance {}\n    aperture {}", focal_distance, aperture)?;
+                match bokeh {
+                    Bokeh::Circle => writeln!(out, "    bokeh circle")?,
+                    Bokeh::Polygon { blades } => writeln!(out, "    bokeh polygon {}", blades)?,
+                }
+            }
+            Effect::Outline { threshold, normal_weight, color } => {
+                writeln!(out, "    threshold {}\n    normal_weight {}", threshold, normal_weight)?;
+                writeln!(out, "    color {}", vec3(*color))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_takes_out_parent_components() {
+        assert_eq!(resolve(Path::new("scenes"), "../obj/head.obj"), "obj/head.obj");
+        assert_eq!(resolve(Path::new("/data/scenes"), "./maps/../head.png"), "/data/scenes/head.png");
+        assert_eq!(resolve(Path::new(".."), "../head.png"), "../../head.png");
+        assert_eq!(resolve(Path::new(""), "head.png"), "head.png");
+        assert_eq!(resolve(Path::new("scenes"), "/data/head.png"), "/data/head.png");
+    }
+
+    #[test]
+    fn default_scene_round_trips() {
+        let scene = load_scene(DEFAULT_SCENE).unwrap();
+        let object = &scene.state.objects[0];
+        assert_eq!(object.mesh.path.as_deref(), Some("obj/african_head.obj"));
+        assert_eq!(object.material.normal.path.as_deref(), Some("obj/african_head_nm_tangent.png"));
+        assert_eq!(scene.size, Some((1000, 1000)));
+
+        let dir = scene_dir(DEFAULT_SCENE);
+        let text = write_scene(&scene.state, scene.size, dir);
+        assert!(text.contains("\nobject ../obj/african_head.obj\n"), "{}", text);
+        assert!(text.contains("\n    alternate_normal ../obj/african_head_nm.png\n"), "{}", text);
+
+        let reparsed = parse_scene(&text, dir).unwrap();
+        assert_eq!(write_scene(&reparsed.state, reparsed.size, dir), text);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let error = parse_scene("render\n    shader none\n", Path::new("")).err().unwrap();
+        assert!(error.starts_with("line 2:"), "{}", error);
+    }
+}
@@ -0,0 +1,642 @@
+use std::f32::consts::PI;
+
+use glam::Vec4Swizzles;
+
+use crate::geometry::{Vector2, Vector3, Vector4, Matrix3, Matrix4};
+use crate::environment::Environment;
+use crate::fog::Fog;
+use crate::light::Light;
+use crate::renderer::{Material, NormalSpace, Texture, Vertex};
+use crate::util::{color_from_vec4, color_from_linear, srgb_to_linear, vec4_from_color, vec4_gl_from_color, vec3_normal_from_color};
+
+/// Values output per vertex by a vertex shader and interpolated across the
+/// triangle by the rasterizer.  Structs of varyings can implement this with
+/// `impl_varying!`.
+pub trait Varying: Copy {
+    fn scale(self, s: f32) -> Self;
+    fn accumulate(self, other: Self) -> Self;
+
+    //  linear interpolation, used when clipping edges
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self.scale(1.0 - t).accumulate(other.scale(t))
+    }
+
+    //  weighted sum of a triangle's varyings by barycentric coords
+    fn interpolate(vs: &[Self; 3], bar: Vector3) -> Self {
+        vs[0].scale(bar.x)
+            .accumulate(vs[1].scale(bar.y))
+            .accumulate(vs[2].scale(bar.z))
+    }
+}
+
+macro_rules! impl_varying_vector {
+    ($($t:ty),*) => {
+        $(
+            impl Varying for $t {
+                fn scale(self, s: f32) -> Self { self * s }
+                fn accumulate(self, other: Self) -> Self { self + other }
+            }
+        )*
+    };
+}
+
+impl_varying_vector!(f32, Vector2, Vector3, Vector4);
+
+impl Varying for () {
+    fn scale(self, _s: f32) -> Self {}
+    fn accumulate(self, _other: Self) -> Self {}
+}
+
+/// Implement `Varying` for a struct whose fields are all `Varying`, e.g.
+/// `impl_varying!(PhongVarying { clip, normal, uv });`
+#[macro_export]
+macro_rules! impl_varying {
+    ($t:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::shader::Varying for $t {
+            fn scale(self, s: f32) -> Self {
+                Self { $($field: $crate::shader::Varying::scale(self.$field, s)),* }
+            }
+
+            fn accumulate(self, other: Self) -> Self {
+                Self { $($field: $crate::shader::Varying::accumulate(self.$field, other.$field)),* }
+            }
+        }
+    };
+}
+
+/// Input to a fragment shader
+pub struct Fragment<'a, V> {
+    /// Window coordinates of the pixel
+    pub coord: Vector2,
+    /// Depth that will be written to the zbuf
+    pub depth: f32,
+    /// Perspective-correct interpolated varyings
+    pub varying: V,
+    /// Varyings at the vertices of the (clipped) triangle being rasterized
+    pub triangle: &'a [V; 3],
+}
+
+pub trait Shader {
+    type Attribute;
+    type Varying: Varying;
+
+    //  returns the clip space position and the varyings of a vertex
+    fn vertex(&self, attribute: &Self::Attribute) -> (Vector4, Self::Varying);
+    //  writes the fragment color, returns true to discard the fragment
+    fn fragment(&self, frag: &Fragment<Self::Varying>, color: &mut u32) -> bool;
+
+    //  surface attributes of a drawn fragment, written to the G-buffer for
+    //  screen space passes like SSAO and deferred lighting
+    fn surface(&self, _frag: &Fragment<Self::Varying>) -> Option<Surface> {
+        None
+    }
+
+    //  `fragment` and `surface` together, only called while the G-buffer is
+    //  written.  Shaders whose surface costs as much as shading, like a
+    //  mapped normal, override it to share the work.
+    fn fragment_surface(&self, frag: &Fragment<Self::Varying>, color: &mut u32) -> (bool, Option<Surface>) {
+        (self.fragment(frag, color), self.surface(frag))
+    }
+}
+
+/// Attributes of the visible surface at a pixel, as stored in the G-buffer
+#[derive(Clone, Copy)]
+pub struct Surface {
+    //  world space position and unit normal
+    pub position: Vector3,
+    pub normal: Vector3,
+    //  sRGB base color in [0, 1], the lighting pass converts it to linear
+    //  once per pixel rather than per fragment
+    pub albedo: Vector3,
+    //  metallic and roughness with the material's texture applied
+    pub metallic: f32,
+    pub roughness: f32,
+    //  index of the surface's material, shown by the material view
+    pub material: u32,
+}
+
+/// The built-in shaders, selectable in the GUI
+#[derive(Clone, Copy, PartialEq)]
+pub enum ShaderKind {
+    Flat,
+    Gouraud,
+    Phong,
+    NormalMapped,
+    Toon,
+    Unlit,
+    Pbr,
+}
+
+impl ShaderKind {
+    pub const ALL: [ShaderKind; 7] = [
+        ShaderKind::Flat,
+        ShaderKind::Gouraud,
+        ShaderKind::Phong,
+        ShaderKind::NormalMapped,
+        ShaderKind::Toon,
+        ShaderKind::Unlit,
+        ShaderKind::Pbr,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShaderKind::Flat => "Flat",
+            ShaderKind::Gouraud => "Gouraud",
+            ShaderKind::Phong => "Phong",
+            ShaderKind::NormalMapped => "Normal mapped",
+            ShaderKind::Toon => "Toon",
+            ShaderKind::Unlit => "Unlit",
+            ShaderKind::Pbr => "PBR",
+        }
+    }
+}
+
+/// Uniforms shared by the built-in shaders.  Lighting is done in world space.
+#[derive(Clone, Copy)]
+pub struct Uniforms<'a> {
+    pub projection: Matrix4,
+    pub view: Matrix4,
+    pub model: Matrix4,
+    //  inverse transpose of the model matrix, transforms normals to world space
+    pub normal_matrix: Matrix3,
+    //  world space camera position
+    pub eye: Vector3,
+    pub lights: &'a [Light],
+    pub material: &'a Material,
+    pub environment: Option<&'a Environment>,
+    //  fog applied per fragment by the built-in shaders
+    pub fog: Option<Fog>,
+}
+
+impl<'a> Uniforms<'a> {
+    pub fn new(projection: Matrix4, view: Matrix4, model: Matrix4, eye: Vector3, lights: &'a [Light], material: &'a Material) -> Self {
+        Self {
+            projection,
+            view,
+            model,
+            normal_matrix: Matrix3::from_mat4(model).inverse().transpose(),
+            eye,
+            lights,
+            material,
+            environment: None,
+            fog: None,
+        }
+    }
+
+    pub fn world_position(&self, position: Vector3) -> Vector3 {
+        self.model.transform_point3(position)
+    }
+
+    pub fn world_normal(&self, normal: Vector3) -> Vector3 {
+        self.normal_matrix * normal
+    }
+
+    //  tangents transform with the model matrix, handedness is preserved
+    pub fn world_tangent(&self, tangent: Vector4) -> Vector4 {
+        self.model.transform_vector3(tangent.xyz()).extend(tangent.w)
+    }
+
+    //  clip space position of a world space point
+    pub fn clip(&self, world: Vector3) -> Vector4 {
+        self.projection * self.view * world.extend(1.0)
+    }
+
+    //  sum over the lights of the light arriving at a world position scaled by
+    //  the response to the cosine between the normal and the light direction
+    pub fn lighting_with(&self, position: Vector3, n: Vector3, response: impl Fn(f32) -> f32) -> Vector3 {
+        self.lights.iter()
+            .map(|light| {
+                let (l, radiance) = light.incident(position);
+                radiance * response(Vector3::dot(n, l))
+            })
+            .fold(Vector3::ZERO, |l, r| l + r)
+    }
+
+    pub fn lambert(&self, position: Vector3, n: Vector3) -> Vector3 {
+        self.lighting_with(position, n, |cos| f32::max(0.0, cos)) + self.ambient(n)
+    }
+
+    //  light reflected by a white Lambertian surface from the environment
+    pub fn ambient(&self, n: Vector3) -> Vector3 {
+        match self.environment {
+            Some(environment) => environment.irradiance(n) / PI,
+            None => Vector3::ZERO,
+        }
+    }
+
+    //  world space normal from the material's normal map
+    pub fn mapped_normal(&self, varying: &PhongVarying) -> Vector3 {
+        let uv = varying.uv;
+        let sample = vec3_normal_from_color(self.material.normal.sample_nn(uv.x, uv.y));
+
+        match self.material.normal_space {
+            NormalSpace::Tangent => tangent_normal(varying, sample),
+            NormalSpace::Object => self.world_normal(sample).normalize(),
+        }
+    }
+
+    //  the fragment color at a world position with fog applied, if any
+    pub fn fogged(&self, position: Vector3, color: u32) -> u32 {
+        match &self.fog {
+            Some(fog) => fog.apply(self.eye, position, color),
+            None => color,
+        }
+    }
+
+    pub fn albedo(&self, uv: Vector2) -> Vector3 {
+        vec4_from_color(self.material.diffuse.sample_lerp(uv.x, uv.y)).xyz()
+    }
+
+    //  metallic and roughness factors times the metallic-roughness texture, clamped
+    pub fn metallic_roughness(&self, uv: Vector2) -> (f32, f32) {
+        let mr = sample_or_one(self.material.metallic_roughness.as_deref(), uv);
+        ((mr.z * self.material.metallic).clamp(0.0, 1.0), (mr.y * self.material.roughness).clamp(0.045, 1.0))
+    }
+
+    //  G-buffer surface of the built-in shaders, which draw material 0
+    pub fn surface(&self, position: Vector3, normal: Vector3, uv: Vector2) -> Surface {
+        let (metallic, roughness) = self.metallic_roughness(uv);
+        Surface {
+            position,
+            normal,
+            albedo: self.albedo(uv) / 255.0,
+            metallic,
+            roughness,
+            material: 0,
+        }
+    }
+}
+
+fn opaque(c: Vector3) -> u32 {
+    color_from_vec4(c.clamp(Vector3::ZERO, Vector3::splat(255.0)).extend(255.0))
+}
+
+#[derive(Clone, Copy)]
+pub struct FlatVarying {
+    pub position: Vector3,
+    pub uv: Vector2,
+}
+
+impl_varying!(FlatVarying { position, uv });
+
+/// Lambert shading with the face normal of each triangle
+pub struct FlatShader<'a> {
+    pub uniforms: Uniforms<'a>,
+}
+
+impl Shader for FlatShader<'_> {
+    type Attribute = Vertex;
+    type Varying = FlatVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, FlatVarying) {
+        let position = self.uniforms.world_position(attribute.position);
+        let varying = FlatVarying { position, uv: attribute.uv };
+        (self.uniforms.clip(position), varying)
+    }
+
+    fn fragment(&self, frag: &Fragment<FlatVarying>, color: &mut u32) -> bool {
+        let n = face_normal(frag);
+        let c = opaque(self.uniforms.albedo(frag.varying.uv) * self.uniforms.lambert(frag.varying.position, n));
+        *color = self.uniforms.fogged(frag.varying.position, c);
+
+        false
+    }
+
+    fn surface(&self, frag: &Fragment<FlatVarying>) -> Option<Surface> {
+        Some(self.uniforms.surface(frag.varying.position, face_normal(frag), frag.varying.uv))
+    }
+}
+
+fn face_normal(frag: &Fragment<FlatVarying>) -> Vector3 {
+    let [p0, p1, p2] = frag.triangle.map(|v| v.position);
+    Vector3::cross(p1 - p0, p2 - p0).normalize()
+}
+
+#[derive(Clone, Copy)]
+pub struct GouraudVarying {
+    pub light: Vector3,
+    pub position: Vector3,
+    pub normal: Vector3,
+    pub uv: Vector2,
+}
+
+impl_varying!(GouraudVarying { light, position, normal, uv });
+
+/// Lambert shading evaluated per vertex and interpolated
+pub struct GouraudShader<'a> {
+    pub uniforms: Uniforms<'a>,
+}
+
+impl Shader for GouraudShader<'_> {
+    type Attribute = Vertex;
+    type Varying = GouraudVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, GouraudVarying) {
+        let position = self.uniforms.world_position(attribute.position);
+        let normal = self.uniforms.world_normal(attribute.normal).normalize();
+        let varying = GouraudVarying {
+            light: self.uniforms.lambert(position, normal),
+            position,
+            normal,
+            uv: attribute.uv,
+        };
+        (self.uniforms.clip(position), varying)
+    }
+
+    fn fragment(&self, frag: &Fragment<GouraudVarying>, color: &mut u32) -> bool {
+        let c = opaque(self.uniforms.albedo(frag.varying.uv) * frag.varying.light);
+        *color = self.uniforms.fogged(frag.varying.position, c);
+
+        false
+    }
+
+    fn surface(&self, frag: &Fragment<GouraudVarying>) -> Option<Surface> {
+        let v = &frag.varying;
+        Some(self.uniforms.surface(v.position, v.normal.normalize(), v.uv))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PhongVarying {
+    pub position: Vector3,
+    pub normal: Vector3,
+    pub tangent: Vector4,
+    pub uv: Vector2,
+}
+
+impl_varying!(PhongVarying { position, normal, tangent, uv });
+
+pub(crate) fn phong_vertex(uniforms: &Uniforms, attribute: &Vertex) -> (Vector4, PhongVarying) {
+    let varying = PhongVarying {
+        position: uniforms.world_position(attribute.position),
+        normal: uniforms.world_normal(attribute.normal),
+        tangent: uniforms.world_tangent(attribute.tangent),
+        uv: attribute.uv,
+    };
+    (uniforms.clip(varying.position), varying)
+}
+
+/// Lambert shading with per-fragment interpolated normals
+pub struct PhongShader<'a> {
+    pub uniforms: Uniforms<'a>,
+}
+
+impl Shader for PhongShader<'_> {
+    type Attribute = Vertex;
+    type Varying = PhongVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, PhongVarying) {
+        phong_vertex(&self.uniforms, attribute)
+    }
+
+    fn fragment(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> bool {
+        let n = frag.varying.normal.normalize();
+        let c = opaque(self.uniforms.albedo(frag.varying.uv) * self.uniforms.lambert(frag.varying.position, n));
+        *color = self.uniforms.fogged(frag.varying.position, c);
+
+        false
+    }
+
+    fn surface(&self, frag: &Fragment<PhongVarying>) -> Option<Surface> {
+        let v = &frag.varying;
+        Some(self.uniforms.surface(v.position, v.normal.normalize(), v.uv))
+    }
+}
+
+/// Phong shading with normals from a tangent or object space normal map
+pub struct NormalMappedShader<'a> {
+    pub uniforms: Uniforms<'a>,
+}
+
+impl Shader for NormalMappedShader<'_> {
+    type Attribute = Vertex;
+    type Varying = PhongVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, PhongVarying) {
+        phong_vertex(&self.uniforms, attribute)
+    }
+
+    fn fragment(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> bool {
+        self.shade(frag, color);
+        false
+    }
+
+    fn fragment_surface(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> (bool, Option<Surface>) {
+        let v = &frag.varying;
+        let n = self.shade(frag, color);
+        (false, Some(self.uniforms.surface(v.position, n, v.uv)))
+    }
+}
+
+impl NormalMappedShader<'_> {
+    //  writes the color, returns the mapped normal
+    fn shade(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> Vector3 {
+        let n = self.uniforms.mapped_normal(&frag.varying);
+        let c = opaque(self.uniforms.albedo(frag.varying.uv) * self.uniforms.lambert(frag.varying.position, n));
+        *color = self.uniforms.fogged(frag.varying.position, c);
+        n
+    }
+}
+
+//  transform a tangent space sample by the interpolated TBN basis
+fn tangent_normal(varying: &PhongVarying, sample: Vector3) -> Vector3 {
+    let n = varying.normal.normalize();
+    let t = varying.tangent.xyz();
+    let t = (t - n * Vector3::dot(n, t)).normalize();
+    let b = Vector3::cross(n, t) * varying.tangent.w.signum();
+
+    (Matrix3::from_cols(t, b, n) * sample).normalize()
+}
+
+/// Phong shading with the diffuse intensity of each light quantized into bands
+pub struct ToonShader<'a> {
+    pub uniforms: Uniforms<'a>,
+    pub bands: u32,
+}
+
+impl Shader for ToonShader<'_> {
+    type Attribute = Vertex;
+    type Varying = PhongVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, PhongVarying) {
+        phong_vertex(&self.uniforms, attribute)
+    }
+
+    fn fragment(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> bool {
+        let bands = self.bands.max(1) as f32;
+        //  round up so only surfaces facing away from a light are unlit by it
+        let n = frag.varying.normal.normalize();
+        let toon = self.uniforms.lighting_with(frag.varying.position, n, |cos| {
+            (f32::max(0.0, cos) * bands).ceil() / bands
+        }) + self.uniforms.ambient(n);
+        *color = self.uniforms.fogged(frag.varying.position, opaque(self.uniforms.albedo(frag.varying.uv) * toon));
+
+        false
+    }
+
+    fn surface(&self, frag: &Fragment<PhongVarying>) -> Option<Surface> {
+        let v = &frag.varying;
+        Some(self.uniforms.surface(v.position, v.normal.normalize(), v.uv))
+    }
+}
+
+/// Diffuse texture without lighting
+pub struct UnlitShader<'a> {
+    pub uniforms: Uniforms<'a>,
+}
+
+impl Shader for UnlitShader<'_> {
+    type Attribute = Vertex;
+    type Varying = FlatVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, FlatVarying) {
+        let position = self.uniforms.world_position(attribute.position);
+        (self.uniforms.clip(position), FlatVarying { position, uv: attribute.uv })
+    }
+
+    fn surface(&self, frag: &Fragment<FlatVarying>) -> Option<Surface> {
+        Some(self.uniforms.surface(frag.varying.position, face_normal(frag), frag.varying.uv))
+    }
+
+    fn fragment(&self, frag: &Fragment<FlatVarying>, color: &mut u32) -> bool {
+        *color = self.uniforms.fogged(frag.varying.position, opaque(self.uniforms.albedo(frag.varying.uv)));
+
+        false
+    }
+}
+
+//  [0, 1] channels of an optional texture, or ones when absent
+fn sample_or_one(texture: Option<&Texture>, uv: Vector2) -> Vector4 {
+    match texture {
+        Some(t) => vec4_gl_from_color(t.sample_lerp(uv.x, uv.y)),
+        None => Vector4::ONE,
+    }
+}
+
+//  GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+//  Smith geometry term with the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let g1 = |x: f32| x / (x * (1.0 - k) + k);
+    g1(n_dot_v) * g1(n_dot_l)
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: Vector3) -> Vector3 {
+    f0 + (Vector3::ONE - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+//  Fresnel averaged over the specular lobe, for ambient lighting
+fn fresnel_schlick_roughness(cos_theta: f32, f0: Vector3, roughness: f32) -> Vector3 {
+    f0 + (Vector3::splat(1.0 - roughness).max(f0) - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+//  Karis' analytic fit of the split-sum environment BRDF, returns F0 * A + B
+fn env_brdf_approx(f0: Vector3, roughness: f32, n_dot_v: f32) -> Vector3 {
+    let c0 = Vector4::new(-1.0, -0.0275, -0.572, 0.022);
+    let c1 = Vector4::new(1.0, 0.0425, 1.04, -0.04);
+    let r = c0 * roughness + c1;
+    let a004 = f32::min(r.x * r.x, (-9.28 * n_dot_v).exp2()) * r.x + r.y;
+    let a = -1.04 * a004 + r.z;
+    let b = 1.04 * a004 + r.w;
+    f0 * a + Vector3::splat(b)
+}
+
+impl Uniforms<'_> {
+    /// Linear radiance reflected towards the eye by a metallic-roughness
+    /// surface from the lights and the environment (or a constant ambient)
+    pub fn cook_torrance(&self, position: Vector3, n: Vector3, base_color: Vector3, metallic: f32, roughness: f32, occlusion: f32) -> Vector3 {
+        let v = (self.eye - position).normalize_or_zero();
+        let n_dot_v = Vector3::dot(n, v).max(1.0e-4);
+        let f0 = Vector3::lerp(Vector3::splat(0.04), base_color, metallic);
+        let alpha = roughness * roughness;
+
+        let direct = self.lights.iter()
+            .map(|light| {
+                let (l, radiance) = light.incident(position);
+                let n_dot_l = Vector3::dot(n, l);
+                if n_dot_l <= 0.0 {
+                    return Vector3::ZERO;
+                }
+                let h = (v + l).normalize_or_zero();
+                let f = fresnel_schlick(Vector3::dot(h, v), f0);
+                let specular = f * distribution_ggx(Vector3::dot(n, h).max(0.0), alpha)
+                    * geometry_smith(n_dot_v, n_dot_l, roughness)
+                    / (4.0 * n_dot_v * n_dot_l);
+                //  energy reflected specularly isn't available for diffuse
+                let kd = (Vector3::ONE - f) * (1.0 - metallic);
+
+                (kd * base_color / PI + specular) * radiance * PI * n_dot_l
+            })
+            .fold(Vector3::ZERO, |l, r| l + r);
+
+        let ambient = match self.environment {
+            Some(environment) => {
+                let kd = (Vector3::ONE - fresnel_schlick_roughness(n_dot_v, f0, roughness)) * (1.0 - metallic);
+                let diffuse = kd * base_color * environment.irradiance(n) / PI;
+                let r = 2.0 * Vector3::dot(n, v) * n - v;
+                let specular = environment.specular(r, roughness) * env_brdf_approx(f0, roughness, n_dot_v);
+                (diffuse + specular) * occlusion
+            }
+            None => base_color * PBR_AMBIENT * occlusion,
+        };
+
+        direct + ambient
+    }
+}
+
+/// Cook-Torrance GGX metallic-roughness shading in linear HDR, tonemapped
+/// to sRGB on output, with image-based ambient light from the environment.
+/// Lights are scaled by PI so a white Lambertian surface facing a light
+/// reflects its intensity, as in the other built-in shaders.
+pub struct PbrShader<'a> {
+    pub uniforms: Uniforms<'a>,
+    pub exposure: f32,
+}
+
+//  constant ambient light without an environment, occluded by the occlusion texture
+const PBR_AMBIENT: f32 = 0.03;
+
+impl Shader for PbrShader<'_> {
+    type Attribute = Vertex;
+    type Varying = PhongVarying;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vector4, PhongVarying) {
+        phong_vertex(&self.uniforms, attribute)
+    }
+
+    fn fragment(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> bool {
+        self.shade(frag, color);
+        false
+    }
+
+    fn fragment_surface(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> (bool, Option<Surface>) {
+        let v = &frag.varying;
+        let n = self.shade(frag, color);
+        (false, Some(self.uniforms.surface(v.position, n, v.uv)))
+    }
+}
+
+impl PbrShader<'_> {
+    //  writes the color, returns the mapped normal
+    fn shade(&self, frag: &Fragment<PhongVarying>, color: &mut u32) -> Vector3 {
+        let material = self.uniforms.material;
+        let uv = frag.varying.uv;
+        let position = frag.varying.position;
+
+        let base_color = srgb_to_linear(vec4_gl_from_color(material.diffuse.sample_lerp(uv.x, uv.y)).xyz());
+        let (metallic, roughness) = self.uniforms.metallic_roughness(uv);
+        let occlusion = sample_or_one(material.occlusion.as_deref(), uv).x;
+        let emissive = srgb_to_linear(sample_or_one(material.emissive.as_deref(), uv).xyz()) * material.emissive_factor;
+
+        let n = self.uniforms.mapped_normal(&frag.varying);
+        let lit = self.uniforms.cook_torrance(position, n, base_color, metallic, roughness, occlusion);
+        *color = self.uniforms.fogged(position, color_from_linear(lit + emissive, self.exposure));
+        n
+    }
+}
@@ -0,0 +1,143 @@
+use glam::Vec4Swizzles;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::geometry::{Matrix3, Vector3};
+use crate::mesh::perpendicular;
+use crate::renderer::{Camera, Renderer, unproject};
+use crate::util::{buf_index_yinvert, color_from_vec4, vec4_from_color};
+
+//  side of the tile of per-pixel kernel rotations, blurred away afterwards
+const NOISE_SIZE: usize = 4;
+
+/// Screen space ambient occlusion, applied to the frame after shading
+#[derive(Clone, Copy, PartialEq)]
+pub struct Ssao {
+    pub enabled: bool,
+    //  world space radius of the hemisphere sampled around each pixel
+    pub radius: f32,
+    pub samples: u32,
+    //  how much fully occluded pixels are darkened, in [0, 1]
+    pub strength: f32,
+    //  world space distance a sample must be behind the surface to count
+    pub bias: f32,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.3,
+            samples: 16,
+            strength: 1.0,
+            bias: 0.02,
+        }
+    }
+}
+
+//  samples in the unit hemisphere around +z, denser close to the origin
+fn hemisphere_kernel(samples: u32, rng: &mut StdRng) -> Vec<Vector3> {
+    (0..samples).map(|i| {
+        let v = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(0.0..1.0));
+        let t = i as f32 / samples as f32;
+        v.normalize_or_zero() * rng.gen_range(0.0..1.0) * (0.1 + 0.9 * t * t)
+    }).collect()
+}
+
+impl Renderer {
+    /// Fill `ao` with the ambient occlusion of each pixel: positions are
+    /// reconstructed from the zbuf and a hemisphere around the normal in
+    /// the G-buffer is sampled for points hidden behind the depth buffer.  Pixels
+    /// without a surface, like the background, are unoccluded.
+    pub fn compute_ssao(&mut self, camera: &Camera, ssao: &Ssao) {
+        let world_to_screen = self.world_to_screen(camera);
+        let screen_to_world = world_to_screen.inverse();
+        let eye = camera.position();
+        let (width, height) = (self.width, self.height);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let kernel = hemisphere_kernel(ssao.samples.max(1), &mut rng);
+        let noise: Vec<Vector3> = (0..NOISE_SIZE * NOISE_SIZE)
+            .map(|_| Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0))
+            .collect();
+
+        let mut occlusion = vec![1.0; self.ao.len()];
+        for (i, ao) in occlusion.iter_mut().enumerate() {
+            if !self.gbuffer.covered(i) {
+                continue;
+            }
+            let n = self.gbuffer.normal[i];
+            let (x, y) = (i as i32 % width, height - 1 - i as i32 / width);
+            let p = unproject(screen_to_world, x, y, self.zbuf[i]);
+
+            //  randomly rotated basis around the normal
+            let r = noise[(y as usize % NOISE_SIZE) * NOISE_SIZE + x as usize % NOISE_SIZE];
+            let mut t = (r - n * Vector3::dot(n, r)).normalize_or_zero();
+            if t == Vector3::ZERO {
+                t = perpendicular(n);
+            }
+            let tbn = Matrix3::from_cols(t, Vector3::cross(n, t), n);
+
+            let occluded: f32 = kernel.iter().map(|k| {
+                let s = p + tbn * *k * ssao.radius;
+                let c = world_to_screen * s.extend(1.0);
+                if c.w <= 0.0 {
+                    return 0.0;
+                }
+                let (sx, sy) = ((c.x / c.w).round() as i32, (c.y / c.w).round() as i32);
+                if sx < 0 || sx >= width || sy < 0 || sy >= height {
+                    return 0.0;
+                }
+                let j = buf_index_yinvert(sx, sy, width, height);
+                if !self.gbuffer.covered(j) {
+                    return 0.0;
+                }
+
+                //  occluded when the surface seen there is in front of the sample,
+                //  fading out surfaces much further away than the radius
+                let q = unproject(screen_to_world, sx, sy, self.zbuf[j]);
+                if (q - eye).length() < (s - eye).length() - ssao.bias {
+                    let range = ssao.radius / (p - q).length().max(f32::EPSILON);
+                    range.clamp(0.0, 1.0)
+                } else {
+                    0.0
+                }
+            }).sum();
+            *ao = 1.0 - occluded / kernel.len() as f32;
+        }
+
+        //  box blur over the noise tile, ignoring pixels without geometry
+        let half = (NOISE_SIZE / 2) as i32;
+        for (i, ao) in self.ao.iter_mut().enumerate() {
+            if !self.gbuffer.covered(i) {
+                *ao = 1.0;
+                continue;
+            }
+            let (x, y) = (i as i32 % width, i as i32 / width);
+            let (mut sum, mut count) = (0.0, 0);
+            for by in (y - half).max(0)..(y + half).min(height) {
+                for bx in (x - half).max(0)..(x + half).min(width) {
+                    let j = (by * width + bx) as usize;
+                    if self.gbuffer.covered(j) {
+                        sum += occlusion[j];
+                        count += 1;
+                    }
+                }
+            }
+            *ao = sum / count.max(1) as f32;
+        }
+    }
+
+    /// Darken the frame by the ambient occlusion in `ao`
+    pub fn apply_ssao(&mut self, strength: f32) {
+        for (pixel, ao) in self.buf.iter_mut().zip(self.ao.iter()) {
+            let c = vec4_from_color(*pixel);
+            let shade = 1.0 - strength.clamp(0.0, 1.0) * (1.0 - ao);
+            *pixel = color_from_vec4((c.xyz() * shade).extend(c.w));
+        }
+    }
+
+    //  the ambient occlusion as grayscale, for display
+    pub fn ao_buf(&self) -> Vec<u32> {
+        self.ao.iter().map(|ao| color_from_vec4((Vector3::splat(ao * 255.0)).extend(255.0))).collect()
+    }
+}
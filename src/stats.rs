@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+/// A timed part of drawing a frame
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stage {
+    Background,
+    /// Running the vertex shader and clipping, for the objects or the G-buffer
+    Vertex,
+    /// Covering pixels with triangles and depth testing them
+    Raster,
+    /// Running the fragment shader and writing the fragments kept
+    Fragment,
+    /// The deferred lighting pass
+    Lighting,
+    Ssao,
+    Fog,
+    PostProcess,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 8] = [
+        Stage::Background, Stage::Vertex, Stage::Raster, Stage::Fragment,
+        Stage::Lighting, Stage::Ssao, Stage::Fog, Stage::PostProcess,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Background => "Background",
+            Stage::Vertex => "Vertex",
+            Stage::Raster => "Rasterization",
+            Stage::Fragment => "Fragment",
+            Stage::Lighting => "Lighting",
+            Stage::Ssao => "Ambient occlusion",
+            Stage::Fog => "Fog",
+            Stage::PostProcess => "Post-processing",
+        }
+    }
+}
+
+/// Counters and timings of the last frame drawn by `Renderer::draw_mesh_shader`.
+/// Triangles are counted as submitted, before clipping, and as rasterized,
+/// after the near plane clipped some of them into several.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct RenderStats {
+    pub triangles: u64,
+    /// Entirely behind the near plane, so not drawn
+    pub behind_near: u64,
+    /// Partly behind the near plane
+    pub clipped: u64,
+    /// Covering part of the frame
+    pub rasterized: u64,
+    /// Pixels covered by rasterized triangles
+    pub fragments: u64,
+    /// Fragments behind what was already drawn at their pixel
+    pub depth_rejected: u64,
+    /// Fragments passing the depth test that the fragment shader kept
+    pub shaded: u64,
+    /// Fragments passing the depth test that the fragment shader discarded
+    pub discarded: u64,
+    /// Shaded fragments that were drawn over an earlier one at their pixel
+    pub overdraw: u64,
+    pub stage_times: [Duration; Stage::ALL.len()],
+    pub total: Duration,
+}
+
+impl RenderStats {
+    pub fn stage_time(&self, stage: Stage) -> Duration {
+        self.stage_times[stage as usize]
+    }
+
+    /// Add the time since `start` to the stage
+    pub fn time(&mut self, stage: Stage, start: Instant) {
+        self.stage_times[stage as usize] += start.elapsed();
+    }
+}
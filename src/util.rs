@@ -1,7 +1,8 @@
 use std::path::Path;
 use std::fs::File;
 use std::io::BufWriter;
-use std::ops::{Add, Mul, Sub};
+use std::ops::{Add, Deref, Mul, Sub};
+use std::sync::Arc;
 
 use glam::Vec4Swizzles;
 
@@ -9,20 +10,31 @@ use crate::geometry::{Vector4, Vector3};
 use crate::renderer::Texture;
 
 pub fn load_png_texture(path_str: &str) -> Texture {
-    let decoder = png::Decoder::new(File::open(path_str).unwrap());
-    let mut reader = decoder.read_info().unwrap();
+    read_png_texture(path_str).unwrap()
+}
+
+//  expands palette and 16 bit images to 8 bit channels
+pub fn read_png_texture(path_str: &str) -> Result<Texture, png::DecodingError> {
+    let mut decoder = png::Decoder::new(File::open(path_str)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
     let mut bytebuf = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut bytebuf).unwrap();
+    let info = reader.next_frame(&mut bytebuf)?;
     
-    Texture {
+    Ok(Texture {
         width: info.width as f32,
         height: info.height as f32,
-        buf: bytebuf.chunks_exact(3).map(|b| {
+        buf: bytebuf[..info.buffer_size()].chunks_exact(info.color_type.samples()).map(|b| {
             // let bytes = [b[2], b[1], b[0], 0xff];
-            let bytes = [b[0], b[1], b[2], 0xff];
+            let bytes = match info.color_type {
+                png::ColorType::Rgba => [b[0], b[1], b[2], b[3]],
+                png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => [b[0], b[0], b[0], 0xff],
+                _ => [b[0], b[1], b[2], 0xff],
+            };
             u32::from_be_bytes(bytes)
-        }).collect()
-    }
+        }).collect(),
+        path: Some(path_str.to_string()),
+    })
 }
 
 pub fn vec4_from_color(c: u32) -> Vector4 {
@@ -49,6 +61,30 @@ pub fn color_from_vec4(v: Vector4) -> u32 {
     (r << 24) | (g << 16) | (b << 8) | a
 }
 
+//  sRGB encoded [0, 1] color to linear
+pub fn srgb_to_linear(c: Vector3) -> Vector3 {
+    let f = |x: f32| if x <= 0.04045 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) };
+    Vector3::new(f(c.x), f(c.y), f(c.z))
+}
+
+//  linear [0, 1] color to sRGB encoded
+pub fn linear_to_srgb(c: Vector3) -> Vector3 {
+    let f = |x: f32| if x <= 0.0031308 { x * 12.92 } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 };
+    Vector3::new(f(c.x), f(c.y), f(c.z))
+}
+
+//  Narkowicz's ACES filmic curve, maps linear HDR to [0, 1]
+pub fn tonemap_aces(c: Vector3) -> Vector3 {
+    let (a, b, cc, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    ((c * (c * a + b)) / (c * (c * cc + d) + e)).clamp(Vector3::ZERO, Vector3::ONE)
+}
+
+//  tonemap a linear HDR color and encode it as an opaque sRGB color
+pub fn color_from_linear(c: Vector3, exposure: f32) -> u32 {
+    let srgb = linear_to_srgb(tonemap_aces(c * exposure)) * 255.0;
+    color_from_vec4(srgb.extend(255.0))
+}
+
 //  Can't implement Into/From traits on primitive types ourselves
 pub trait Cast<T>: {
     fn cast(&self) -> T;
@@ -66,21 +102,62 @@ pub fn buf_index<T: Mul<Output = T> + Add<Output = T> + Cast<usize>>(x: T, y: T,
     return (y * stride + x).cast();
 }
 
-pub fn buf_index_yinvert<T: Mul<Output = T> + Add<Output = T> + Sub<Output = T> + Cast<usize>>(x: T, y: T, stride: T, height: T) -> usize {
-    return ((height - y) * stride + x).cast();
+pub fn buf_index_yinvert<T: Mul<Output = T> + Add<Output = T> + Sub<Output = T> + Cast<usize> + From<u8>>(x: T, y: T, stride: T, height: T) -> usize {
+    return ((height - y - T::from(1)) * stride + x).cast();
 }
 
 pub fn save_png(path_str: &str, width: u32, height: u32, buf: &[u32]) {
+    write_png(path_str, width, height, buf).unwrap()
+}
+
+pub fn write_png(path_str: &str, width: u32, height: u32, buf: &[u32]) -> Result<(), png::EncodingError> {
     let path = Path::new(&path_str);
-    let file = File::create(path).unwrap();
+    let file = File::create(path)?;
     let ref mut w = BufWriter::new(file);
 
     let mut encoder = png::Encoder::new(w, width, height);
     encoder.set_color(png::ColorType::Rgba);
     encoder.set_depth(png::BitDepth::Eight);
 
-    let mut writer = encoder.write_header().unwrap();
+    let mut writer = encoder.write_header()?;
     // convert u32 buffer to u8
     let bbuf:Vec<u8> = buf.iter().flat_map(|v| v.to_be_bytes()).collect();
-    writer.write_image_data(&bbuf).unwrap(); // Save
+    writer.write_image_data(&bbuf) // Save
+}
+
+/// A large asset, like a mesh or a texture, shared by the copies of the state
+/// holding it.  Copies are equal while they share it, so comparing and
+/// cloning a `RendererState` doesn't go through its assets.
+pub struct Shared<T>(Arc<T>);
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(value))
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Shared<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
 }